@@ -85,31 +85,31 @@ handle_unknown_distribution() {
     fi
 }
 
-# Install base system dependencies (not Python packages)
+# Install base system dependencies
 install_system_dependencies() {
     echo "Installing system dependencies..."
 
     case $PKG_MANAGER in
         "pacman")
             echo "Installing for Arch Linux..."
-            sudo pacman -Syu --needed --noconfirm python python-pip rustup git jdk-openjdk python-virtualenv
+            sudo pacman -Syu --needed --noconfirm rustup git jdk-openjdk
             ;;
         "dnf")
             echo "Installing for Fedora..."
-            sudo dnf install -y python3 python3-pip rust cargo git java-latest-openjdk python3-virtualenv
+            sudo dnf install -y rust cargo git java-latest-openjdk
             ;;
         "emerge")
             echo "Installing for Gentoo..."
-            sudo emerge --ask dev-lang/python dev-python/pip dev-lang/rust dev-vcs/git virtual/jdk dev-python/virtualenv
+            sudo emerge --ask dev-lang/rust dev-vcs/git virtual/jdk
             ;;
         "zypper")
             echo "Installing for openSUSE..."
-            sudo zypper install -y python3 python3-pip rust cargo git java-latest-openjdk python3-virtualenv
+            sudo zypper install -y rust cargo git java-latest-openjdk
             ;;
         "apt-get")
             echo "Installing for Debian/Ubuntu..."
             sudo apt-get update
-            sudo apt-get install -y python3 python3-pip python3-venv curl git default-jdk
+            sudo apt-get install -y curl git default-jdk pkg-config libssl-dev
             # Install Rust if not available
             if ! command -v rustc &> /dev/null; then
                 curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y
@@ -118,18 +118,16 @@ install_system_dependencies() {
             ;;
         "apk")
             echo "Installing for Alpine Linux..."
-            sudo apk add python3 py3-pip rust cargo git openjdk11 py3-virtualenv
+            sudo apk add rust cargo git openjdk11
             ;;
         "brew")
             echo "Installing for macOS..."
             brew update
-            brew install python rust openjdk git
+            brew install rust openjdk git
             ;;
         *)
             echo "Unsupported package manager: $PKG_MANAGER"
             echo "Please install the following dependencies manually:"
-            echo "- Python 3 and pip"
-            echo "- Python virtualenv"
             echo "- Rust and Cargo"
             echo "- Git"
             echo "- Java JDK"
@@ -138,42 +136,6 @@ install_system_dependencies() {
     esac
 }
 
-# Set up Python virtual environment
-setup_virtual_environment() {
-    echo "Setting up Python virtual environment..."
-
-    # Create virtual environment in AI_api directory if it doesn't exist
-    if [ ! -d "AI_api/venv" ]; then
-        # Try with --upgrade-deps first (newer Python versions)
-        (cd AI_api && python3 -m venv --upgrade-deps venv) || \
-        (cd AI_api && python -m venv --upgrade-deps venv) || \
-        (cd AI_api && python3 -m venv venv) || \
-        (cd AI_api && python -m venv venv)
-
-        # Ensure pip is available in the venv
-        if [ ! -f "AI_api/venv/bin/pip" ]; then
-            echo "Installing pip in virtual environment..."
-            AI_api/venv/bin/python -m ensurepip --upgrade
-        fi
-    fi
-
-    # Activate virtual environment
-    source AI_api/venv/bin/activate
-
-    # Upgrade pip
-    python -m pip install --upgrade pip
-
-    # Install Python dependencies
-    echo "Installing Python packages in virtual environment..."
-    pip install fastapi uvicorn openai pydantic google-genai
-
-    # Deactivate virtual environment
-    deactivate
-}
-
-# The github_api.rs file already uses the correct virtual environment path
-# No modification needed for github_api.rs
-
 # Set up environment variables in shell profile
 configure_environment() {
     echo "Configuring environment variables..."
@@ -181,7 +143,6 @@ configure_environment() {
     # Determine the project root directory
     PROJECT_ROOT=$(pwd)
     JARS_DIR=$(pwd)/jars
-    AI_API_DIR=$(pwd)/AI_api
 
     # Find appropriate shell profile file based on current shell
     SHELL_PROFILE=""
@@ -258,7 +219,6 @@ build_and_install_cli() {
 detect_environment
 handle_unknown_distribution
 install_system_dependencies
-setup_virtual_environment
 build_and_install_cli
 configure_environment
 
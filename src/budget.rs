@@ -0,0 +1,337 @@
+use crate::json_parser::{Payload, SourceFile, Truncation};
+use std::path::Path;
+
+// Tokenizers average about four characters per token for English and Java,
+// close enough to stay inside a context window without shipping a tokenizer
+const CHARS_PER_TOKEN: usize = 4;
+// Stack frames kept per failing test once the test output has to be shortened
+const KEPT_FRAMES: usize = 5;
+// A shorter rest of a file is left out instead of cut
+const MIN_PARTIAL_TOKENS: usize = 100;
+// Room for the "[... N more lines left out ...]" line added when a text is cut
+const MARKER_CHARS: usize = 80;
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+//Estimated tokens of everything the payload puts into the prompt, without the template
+pub fn payload_tokens(payload: &Payload) -> usize {
+    estimate_tokens(&payload.read_me)
+        + files_tokens(&payload.source_files)
+        + files_tokens(&payload.student_tests)
+        + estimate_tokens(test_output(payload))
+}
+
+// The structured summary, or the raw output for payloads from older versions
+pub fn test_output(payload: &Payload) -> &str {
+    if payload.test_summary.is_empty() {
+        &payload.test_results
+    } else {
+        &payload.test_summary
+    }
+}
+
+//Shortens the payload until it fits in `available` tokens and records what was left out in
+//payload.truncation. What is kept, in order: the failing-test output (up to half), the README
+//(up to a quarter if the rest does not fit), the files the failures point at, the other source
+//files, the student's own tests. `budget` is the whole prompt budget, only for the record.
+pub fn fit_payload(payload: &mut Payload, available: usize, budget: usize) {
+    let original = payload_tokens(payload);
+    if original <= available {
+        return;
+    }
+    let mut elided = Vec::new();
+
+    // the full output, a frame that names a file can be among the ones that are cut
+    let failures = test_output(payload).to_string();
+    let output = if payload.test_summary.is_empty() {
+        &mut payload.test_results
+    } else {
+        &mut payload.test_summary
+    };
+    if let Some(note) = shorten_test_output(output, available / 2) {
+        elided.push(format!("test output: {}", note));
+    }
+    let mut left = available.saturating_sub(estimate_tokens(output));
+
+    let rest = files_tokens(&payload.source_files) + files_tokens(&payload.student_tests);
+    let readme_cap = if estimate_tokens(&payload.read_me) + rest <= left {
+        left
+    } else {
+        (available / 4).min(left)
+    };
+    if let Some(note) = truncate_text(&mut payload.read_me, readme_cap) {
+        elided.push(format!("README: {}", note));
+    }
+    left = left.saturating_sub(estimate_tokens(&payload.read_me));
+
+    let mut order: Vec<usize> = (0..payload.source_files.len()).collect();
+    // stable, so the files keep their order within both groups
+    order.sort_by_key(|&i| !is_referenced(&payload.source_files[i].filename, &failures));
+    for i in order {
+        fit_file(&mut payload.source_files[i], &mut left, &mut elided);
+    }
+
+    payload.student_tests.retain(|file| {
+        let tokens = estimate_tokens(&file.content);
+        if tokens <= left {
+            left -= tokens;
+            true
+        } else {
+            elided.push(format!(
+                "{} (student test): left out ({} tokens)",
+                file.filename, tokens
+            ));
+            false
+        }
+    });
+
+    payload.truncation = Some(Truncation {
+        budget_tokens: budget,
+        original_tokens: original,
+        kept_tokens: payload_tokens(payload),
+        elided,
+    });
+}
+
+//Lines for the terminal, the first one is the headline
+pub fn truncation_lines(student: &str, truncation: &Truncation) -> Vec<String> {
+    let mut lines = vec![format!(
+        "✂️  {}'s payload was shortened to fit the prompt budget of {} tokens (~{} of ~{} tokens kept):",
+        student, truncation.budget_tokens, truncation.kept_tokens, truncation.original_tokens
+    )];
+    lines.extend(truncation.elided.iter().map(|e| format!("   - {}", e)));
+    lines
+}
+
+fn files_tokens(files: &[SourceFile]) -> usize {
+    files.iter().map(|f| estimate_tokens(&f.content)).sum()
+}
+
+// A failure names the file in its stack frames (Calc.java:12) or compiler errors
+fn is_referenced(filename: &str, output: &str) -> bool {
+    Path::new(filename)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| output.contains(name))
+}
+
+fn fit_file(file: &mut SourceFile, left: &mut usize, elided: &mut Vec<String>) {
+    let tokens = estimate_tokens(&file.content);
+    if tokens <= *left {
+        *left -= tokens;
+    } else if *left >= MIN_PARTIAL_TOKENS {
+        if let Some(note) = truncate_text(&mut file.content, *left) {
+            elided.push(format!("{}: {}", file.filename, note));
+        }
+        *left = left.saturating_sub(estimate_tokens(&file.content));
+    } else {
+        file.content = format!("// left out to fit the prompt budget ({} tokens)", tokens);
+        elided.push(format!("{}: left out ({} tokens)", file.filename, tokens));
+        *left = left.saturating_sub(estimate_tokens(&file.content));
+    }
+}
+
+//Drops the passing tests and deep stack frames, then cuts the rest if it is still too long.
+//The outcome line and the counts are on top, so they always stay.
+fn shorten_test_output(output: &mut String, max_tokens: usize) -> Option<String> {
+    let original = estimate_tokens(output);
+    if original <= max_tokens {
+        return None;
+    }
+    let mut kept = String::new();
+    let mut passing = 0;
+    let mut dropped_frames = 0;
+    let mut frames = 0;
+    for line in output.lines() {
+        if line.starts_with("[PASS]") {
+            passing += 1;
+            continue;
+        }
+        if line.starts_with('[') {
+            frames = 0;
+        } else if line.trim_start().starts_with("at ") {
+            frames += 1;
+            if frames > KEPT_FRAMES {
+                dropped_frames += 1;
+                continue;
+            }
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if passing > 0 {
+        kept.push_str(&format!("[{} passing tests not listed]\n", passing));
+    }
+    *output = kept;
+    truncate_text(output, max_tokens);
+
+    let mut notes = Vec::new();
+    if passing > 0 {
+        notes.push(format!("{} passing tests", passing));
+    }
+    if dropped_frames > 0 {
+        notes.push(format!("{} stack frames", dropped_frames));
+    }
+    let kept = format!("kept {} of {} tokens", estimate_tokens(output), original);
+    if notes.is_empty() {
+        Some(kept)
+    } else {
+        Some(format!("{} left out, {}", notes.join(" and "), kept))
+    }
+}
+
+//Keeps the head of the text, cut at a line end, with a marker saying how much is missing.
+//None if it already fits.
+fn truncate_text(text: &mut String, max_tokens: usize) -> Option<String> {
+    let original = estimate_tokens(text);
+    if original <= max_tokens {
+        return None;
+    }
+    let max_chars = (max_tokens * CHARS_PER_TOKEN).saturating_sub(MARKER_CHARS);
+    let mut end = text
+        .char_indices()
+        .nth(max_chars)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    if let Some(newline) = text[..end].rfind('\n') {
+        end = newline + 1;
+    }
+    let dropped = text[end..].lines().count();
+    text.truncate(end);
+    text.push_str(&format!(
+        "\n[... {} more lines left out to fit the prompt budget]\n",
+        dropped
+    ));
+    Some(format!(
+        "kept {} of {} tokens",
+        estimate_tokens(text),
+        original
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `tokens` estimated tokens of 40-char lines
+    fn text(tokens: usize) -> String {
+        format!("{}\n", "x".repeat(39)).repeat(tokens * CHARS_PER_TOKEN / 40)
+    }
+
+    fn file(name: &str, tokens: usize) -> SourceFile {
+        SourceFile {
+            filename: name.to_string(),
+            content: text(tokens),
+        }
+    }
+
+    fn payload() -> Payload {
+        let mut summary = "[FAIL] CalcTest.testAdd\n    at Ref.compute(Ref.java:3)\n".to_string();
+        for i in 0..200 {
+            summary.push_str(&format!("[PASS] CalcTest.test{}\n", i));
+        }
+        Payload {
+            user_id: "alice".to_string(),
+            task: "task-1".to_string(),
+            read_me: text(1000),
+            // listed first, but the failure points at Ref.java
+            source_files: vec![file("Other.java", 500), file("Ref.java", 400)],
+            student_tests: vec![file("MyTest.java", 100)],
+            test_results: String::new(),
+            test_summary: summary,
+            test_report: None,
+            commit: None,
+            truncation: None,
+        }
+    }
+
+    #[test]
+    fn a_payload_that_fits_is_left_alone() {
+        let mut small = payload();
+        let before = payload_tokens(&small);
+        fit_payload(&mut small, before, 6000);
+        assert!(small.truncation.is_none());
+        assert_eq!(payload_tokens(&small), before);
+    }
+
+    #[test]
+    fn parts_are_kept_in_priority_order() {
+        let mut payload = payload();
+        let original = payload_tokens(&payload);
+        fit_payload(&mut payload, 1000, 1200);
+
+        // the failure stays, the passing tests are counted instead of listed
+        assert!(payload.test_summary.starts_with("[FAIL] CalcTest.testAdd"));
+        assert!(!payload.test_summary.contains("[PASS]"));
+        assert!(
+            payload
+                .test_summary
+                .contains("[200 passing tests not listed]")
+        );
+        // the README is cut to a quarter of the budget
+        assert!(estimate_tokens(&payload.read_me) <= 250);
+        assert!(
+            payload
+                .read_me
+                .contains("more lines left out to fit the prompt budget]")
+        );
+        // the file the failure points at is complete, the other one is cut
+        assert_eq!(payload.source_files[1].content, text(400));
+        assert!(payload.source_files[0].content.len() < text(500).len());
+        assert!(
+            payload.source_files[0]
+                .content
+                .ends_with("more lines left out to fit the prompt budget]\n")
+        );
+        // the student's tests come last and are dropped
+        assert!(payload.student_tests.is_empty());
+
+        let truncation = payload.truncation.unwrap();
+        assert_eq!(truncation.budget_tokens, 1200);
+        assert_eq!(truncation.original_tokens, original);
+        assert!(truncation.kept_tokens <= 1000);
+        let elided = truncation.elided.join("\n");
+        assert!(
+            elided.contains("test output: 200 passing tests left out"),
+            "{}",
+            elided
+        );
+        assert!(elided.contains("README: kept"), "{}", elided);
+        assert!(elided.contains("Other.java: kept"), "{}", elided);
+        assert!(!elided.contains("Ref.java"), "{}", elided);
+        assert!(
+            elided.contains("MyTest.java (student test): left out (100 tokens)"),
+            "{}",
+            elided
+        );
+    }
+
+    #[test]
+    fn a_file_without_room_is_left_out() {
+        let mut payload = payload();
+        payload.read_me = String::new();
+        fit_payload(&mut payload, 480, 500);
+        // Ref.java fits, the less than 100 tokens left are not worth a partial Other.java
+        assert_eq!(payload.source_files[1].content, text(400));
+        assert_eq!(
+            payload.source_files[0].content,
+            "// left out to fit the prompt budget (500 tokens)"
+        );
+        let elided = payload.truncation.unwrap().elided;
+        assert!(elided.contains(&"Other.java: left out (500 tokens)".to_string()));
+    }
+
+    #[test]
+    fn deep_stack_frames_are_dropped() {
+        let mut output = "[FAIL] T.a\n".to_string();
+        for i in 0..20 {
+            output.push_str(&format!("    at Calc.step{}(Calc.java:{})\n", i, i));
+        }
+        let original = output.clone();
+        let note = shorten_test_output(&mut output, estimate_tokens(&original) - 1).unwrap();
+        assert_eq!(output.lines().count(), 1 + KEPT_FRAMES);
+        assert!(note.starts_with("15 stack frames left out"), "{}", note);
+    }
+}
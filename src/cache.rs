@@ -0,0 +1,62 @@
+use crate::grading::GradeResponse;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//a validated grader answer, reused as long as the key matches
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    provider: String,
+    // RFC3339
+    graded_at: String,
+    grade: GradeResponse,
+}
+
+//One file per student in <output>/cache. The key changes whenever the payload, the prompt
+//(template, config) or the model changes, then the student is graded again.
+pub fn cache_key(payload_json: &str, prompt: &str, provider: &str) -> String {
+    let mut hasher = Sha256::new();
+    // the separators keep ("ab", "c") and ("a", "bc") apart
+    for part in [provider, payload_json, prompt] {
+        hasher.update(part.len().to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn cache_dir(output_dir: &Path) -> PathBuf {
+    output_dir.join("cache")
+}
+
+//The cached answer for the student, None if there is none or it was made for another key.
+//A broken cache file is treated like a missing one.
+pub fn load_cached(output_dir: &Path, student: &str, key: &str) -> Option<GradeResponse> {
+    let path = cache_dir(output_dir).join(format!("{}.json", student));
+    let entry: CacheEntry = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    (entry.key == key).then_some(entry.grade)
+}
+
+pub fn store_cached(
+    output_dir: &Path,
+    student: &str,
+    key: &str,
+    provider: &str,
+    grade: &GradeResponse,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = cache_dir(output_dir);
+    fs::create_dir_all(&dir)?;
+    let entry = CacheEntry {
+        key: key.to_string(),
+        provider: provider.to_string(),
+        graded_at: Local::now().to_rfc3339(),
+        grade: grade.clone(),
+    };
+    fs::write(
+        dir.join(format!("{}.json", student)),
+        serde_json::to_string_pretty(&entry)?,
+    )?;
+    Ok(())
+}
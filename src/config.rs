@@ -0,0 +1,900 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+// Name of the per-course config file. Looked up in the current directory and its parents.
+pub const PROJECT_CONFIG_FILE: &str = "imagi.toml";
+
+// Environment variables that override single config keys.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("IMAGI_FORGE_HOST", "forge.host"),
+    ("IMAGI_API_BASE", "forge.api_base"),
+    ("IMAGI_STUDENT_ORG", "forge.student_org"),
+    ("IMAGI_SOLUTIONS_ORG", "forge.solutions_org"),
+    ("IMAGI_REPO_PATTERN", "forge.repo_pattern"),
+    ("IMAGI_JARS_DIR", "java.jars_dir"),
+    ("IMAGI_LLM_PROVIDER", "llm.provider"),
+    ("IMAGI_OPENAI_BASE_URL", "llm.openai.base_url"),
+    ("IMAGI_GEMINI_BASE_URL", "llm.gemini.base_url"),
+];
+
+//Effective configuration, merged from defaults, config files, env vars and --set overrides.
+//Loaded once in main.rs and passed to every command that talks to the forge or runs Java.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub forge: ForgeConfig,
+    pub java: JavaConfig,
+    pub clone: CloneConfig,
+    pub solutions: SolutionsConfig,
+    pub llm: LlmConfig,
+    pub http: HttpConfig,
+    pub usage: UsageConfig,
+    pub dry_run: DryRunConfig,
+    pub privacy: PrivacyConfig,
+    // task registry, replaces the built-in task-1..task-18 + quicksort list when set
+    pub tasks: Vec<TaskConfig>,
+    // where every key that was not left at its default came from
+    #[serde(skip)]
+    origins: BTreeMap<String, Origin>,
+}
+
+//where the repos live and how they are named
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForgeConfig {
+    pub host: String,
+    pub api_base: String,
+    pub student_org: String,
+    pub solutions_org: String,
+    // {student} and {task} are replaced, e.g. "{student}-{task}" -> "alice-task-5"
+    pub repo_pattern: String,
+}
+
+//location of the JUnit/Hamcrest jars and the limits used for compiling and running the tests
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct JavaConfig {
+    pub jars_dir: PathBuf,
+    // relative jar paths are resolved against jars_dir
+    pub junit_jar: PathBuf,
+    pub hamcrest_jar: PathBuf,
+    // wall-clock limits per student, the process group is killed when they run out
+    pub compile_timeout_secs: u64,
+    pub test_timeout_secs: u64,
+    // passed to the test JVM as -Xmx
+    pub max_heap: String,
+    // address space limit (RLIMIT_AS) for javac and java in MB, 0 disables it
+    pub max_memory_mb: u64,
+}
+
+//how repositories are cloned
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloneConfig {
+    // number of repos cloned at the same time
+    pub workers: usize,
+    // what to do with a repo that was already cloned by an earlier run
+    pub update: UpdateMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateMode {
+    // fetch and fast-forward, refuse if the local branch has diverged
+    FastForward,
+    // fetch and hard reset to the remote branch
+    Reset,
+}
+
+//where `imagi tests` puts the solution repos
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SolutionsConfig {
+    // default for `imagi tests -o`, also used to find the unit tests for `imagi clone`
+    pub dir: Option<PathBuf>,
+}
+
+//where the token usage and cost of every generate run is recorded
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsageConfig {
+    // one JSON line per task and run, shared by all runs so spend can be tracked per course
+    pub ledger: PathBuf,
+}
+
+//--dry-run: GitHub writes go to the outbox instead, cloning, tests and grading run as usual
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DryRunConfig {
+    pub enabled: bool,
+    // one JSON file per request that would have been sent
+    pub outbox: PathBuf,
+}
+
+//what is replaced before a payload is sent to the model, see pseudonym.rs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    pub enabled: bool,
+    // students.txt whose IDs and names ("alice Alice Andersson") are scrubbed from every payload
+    pub roster: Option<PathBuf>,
+    // scrubbed as well, e.g. names of TAs or partners that are not in the roster
+    pub terms: Vec<String>,
+    pub scrub_emails: bool,
+    // secret the pseudonyms are derived from, created on first use
+    pub key_file: PathBuf,
+}
+
+//timeouts and retries for every HTTP call (model APIs and the GitHub API)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    // whole request, models can take a while to answer
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    // retries after the first attempt for timeouts, connection errors, 429, 5xx and rate limits
+    pub max_retries: u32,
+    // first backoff, doubled on every retry up to max_backoff_secs, with random jitter
+    pub initial_backoff_ms: u64,
+    pub max_backoff_secs: u64,
+    // longest Retry-After / X-RateLimit-Reset wait that is honoured, longer ones fail the request
+    pub max_wait_secs: u64,
+    // requests in flight at the same time, over all services
+    pub max_concurrent: usize,
+}
+
+//which model `imagi generate` talks to and how
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    // "openai", "gemini" or "local:<name>", `imagi generate -m` overrides it
+    pub provider: String,
+    // "student", "teacher" or the path of a template file, `imagi generate --prompt` overrides it
+    pub prompt: String,
+    // overrides for the built-in templates: <dir>/<task>/student.txt, then <dir>/student.txt,
+    // and the same for rubric.md
+    pub prompt_dir: Option<PathBuf>,
+    // how often a malformed answer is asked for again before the student is skipped
+    pub max_attempts: u32,
+    pub openai: OpenAiConfig,
+    pub gemini: GeminiConfig,
+    // self-hosted OpenAI-compatible servers (Ollama, llama.cpp, ...), selected with local:<name>
+    pub local: BTreeMap<String, LocalModelConfig>,
+    // USD per million tokens by model name, added to and overriding the built-in prices in usage.rs
+    pub prices: BTreeMap<String, ModelPrice>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+//any server that speaks the OpenAI chat completions API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    pub base_url: String,
+    pub model: String,
+    // name of the environment variable holding the API key
+    pub api_key_env: String,
+    // estimated prompt tokens, larger payloads are shortened (see budget.rs)
+    pub max_prompt_tokens: usize,
+}
+
+//one [llm.local.<name>] entry, a name without an entry uses the defaults
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalModelConfig {
+    // defaults to Ollama on this machine
+    pub base_url: String,
+    // model name the server knows, defaults to <name>
+    pub model: Option<String>,
+    // most local servers do not need a key
+    pub api_key_env: Option<String>,
+    // keep it below the context window the server runs with (Ollama: num_ctx)
+    pub max_prompt_tokens: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeminiConfig {
+    pub base_url: String,
+    pub model: String,
+    pub api_key_env: String,
+    // the context window is far larger, this keeps the cost per student down
+    pub max_prompt_tokens: usize,
+}
+
+//one entry of the task registry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TaskConfig {
+    pub name: String,
+    // repo in the solutions org, defaults to the task name
+    #[serde(default)]
+    pub repo: Option<String>,
+    #[serde(default = "default_solutions_branch")]
+    pub branch: String,
+    // directory inside the solutions repo that holds the unit tests
+    #[serde(default = "default_tests_dir")]
+    pub tests_dir: PathBuf,
+    // other names accepted for --task, e.g. "5" for "task-5"
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+//layer a config value was taken from
+#[derive(Debug, Clone)]
+pub enum Origin {
+    UserFile(PathBuf),
+    ProjectFile(PathBuf),
+    Env(&'static str),
+    Cli,
+}
+
+//what main.rs collects from the global CLI flags
+#[derive(Debug, Default)]
+pub struct ConfigOptions {
+    pub config_file: Option<PathBuf>,
+    pub overrides: Vec<String>,
+}
+
+impl Default for ForgeConfig {
+    fn default() -> Self {
+        ForgeConfig {
+            host: "gits-15.sys.kth.se".to_string(),
+            api_base: "https://gits-15.sys.kth.se/api/v3".to_string(),
+            student_org: "inda-25".to_string(),
+            solutions_org: "inda-master".to_string(),
+            repo_pattern: "{student}-{task}".to_string(),
+        }
+    }
+}
+
+impl Default for JavaConfig {
+    fn default() -> Self {
+        // The install script puts the jars next to the sources, so IMAGI_ROOT/jars is a good guess
+        let jars_dir = env::var("IMAGI_ROOT")
+            .map(|root| PathBuf::from(root).join("jars"))
+            .unwrap_or_else(|_| PathBuf::from("jars"));
+        JavaConfig {
+            jars_dir,
+            junit_jar: PathBuf::from("junit-4.12.jar"),
+            hamcrest_jar: PathBuf::from("hamcrest-core-1.3.jar"),
+            compile_timeout_secs: 60,
+            test_timeout_secs: 30,
+            max_heap: "256m".to_string(),
+            // the JVM reserves a lot more address space than -Xmx, 4 GB leaves room for that
+            max_memory_mb: 4096,
+        }
+    }
+}
+
+fn default_solutions_branch() -> String {
+    "solutions".to_string()
+}
+
+fn default_tests_dir() -> PathBuf {
+    PathBuf::from("src")
+}
+
+// The INDA course layout: task-1..task-18 plus quicksort, tests on the solutions branch in src/.
+fn default_tasks() -> Vec<TaskConfig> {
+    let mut tasks: Vec<TaskConfig> = (1..=18)
+        .map(|n| TaskConfig {
+            name: format!("task-{}", n),
+            repo: None,
+            branch: default_solutions_branch(),
+            tests_dir: default_tests_dir(),
+            aliases: vec![n.to_string()],
+        })
+        .collect();
+    tasks.push(TaskConfig {
+        name: "quicksort".to_string(),
+        repo: None,
+        branch: default_solutions_branch(),
+        tests_dir: default_tests_dir(),
+        aliases: Vec::new(),
+    });
+    tasks
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            forge: ForgeConfig::default(),
+            java: JavaConfig::default(),
+            clone: CloneConfig::default(),
+            solutions: SolutionsConfig::default(),
+            llm: LlmConfig::default(),
+            http: HttpConfig::default(),
+            usage: UsageConfig::default(),
+            dry_run: DryRunConfig::default(),
+            privacy: PrivacyConfig::default(),
+            tasks: default_tasks(),
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        UsageConfig {
+            ledger: data_dir().join("usage.jsonl"),
+        }
+    }
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        PrivacyConfig {
+            enabled: true,
+            roster: None,
+            terms: Vec::new(),
+            scrub_emails: true,
+            key_file: data_dir().join("pseudonym.key"),
+        }
+    }
+}
+
+impl Default for DryRunConfig {
+    fn default() -> Self {
+        DryRunConfig {
+            enabled: false,
+            outbox: PathBuf::from("outbox"),
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout_secs: 120,
+            connect_timeout_secs: 10,
+            max_retries: 4,
+            initial_backoff_ms: 500,
+            max_backoff_secs: 30,
+            max_wait_secs: 300,
+            max_concurrent: 4,
+        }
+    }
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            provider: "openai".to_string(),
+            prompt: "student".to_string(),
+            prompt_dir: None,
+            max_attempts: 3,
+            openai: OpenAiConfig::default(),
+            gemini: GeminiConfig::default(),
+            local: BTreeMap::new(),
+            prices: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for LocalModelConfig {
+    fn default() -> Self {
+        LocalModelConfig {
+            base_url: "http://127.0.0.1:11434/v1".to_string(),
+            model: None,
+            api_key_env: None,
+            max_prompt_tokens: 6_000,
+        }
+    }
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        OpenAiConfig {
+            base_url: "https://api.openai.com/v1".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            api_key_env: "IMAGI_OPENAI_API_KEY".to_string(),
+            // 128k context, the rest is left for the answer
+            max_prompt_tokens: 100_000,
+        }
+    }
+}
+
+impl Default for GeminiConfig {
+    fn default() -> Self {
+        GeminiConfig {
+            base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            model: "gemini-2.5-flash".to_string(),
+            api_key_env: "IMAGI_GEMINI_API_KEY".to_string(),
+            max_prompt_tokens: 200_000,
+        }
+    }
+}
+
+impl Default for CloneConfig {
+    fn default() -> Self {
+        CloneConfig {
+            workers: 8,
+            update: UpdateMode::FastForward,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::UserFile(path) => write!(f, "user config {}", path.display()),
+            Origin::ProjectFile(path) => write!(f, "project config {}", path.display()),
+            Origin::Env(var) => write!(f, "env {}", var),
+            Origin::Cli => write!(f, "--set"),
+        }
+    }
+}
+
+impl ForgeConfig {
+    //repo name for a student's task, built from repo_pattern
+    pub fn repo_name(&self, student: &str, task: &str) -> String {
+        self.repo_pattern
+            .replace("{student}", student)
+            .replace("{task}", task)
+    }
+
+    pub fn student_clone_url(&self, student: &str, task: &str) -> String {
+        format!(
+            "git@{}:{}/{}.git",
+            self.host,
+            self.student_org,
+            self.repo_name(student, task)
+        )
+    }
+
+    pub fn solutions_clone_url(&self, repo: &str) -> String {
+        format!("git@{}:{}/{}.git", self.host, self.solutions_org, repo)
+    }
+
+    //REST endpoint for the issues of a student's repo
+    pub fn issues_url(&self, student: &str, task: &str) -> String {
+        format!(
+            "{}/repos/{}/{}/issues",
+            self.api_base.trim_end_matches('/'),
+            self.student_org,
+            self.repo_name(student, task)
+        )
+    }
+}
+
+impl TaskConfig {
+    pub fn repo(&self) -> &str {
+        self.repo.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+impl JavaConfig {
+    //classpath entries for javac/java besides the compiled classes
+    pub fn jar_paths(&self) -> Vec<PathBuf> {
+        vec![
+            self.jars_dir.join(&self.junit_jar),
+            self.jars_dir.join(&self.hamcrest_jar),
+        ]
+    }
+}
+
+impl Config {
+    //Loads the layers in increasing priority: defaults, user file, project file, env vars, --set.
+    pub fn load(options: &ConfigOptions) -> Result<Config, Box<dyn std::error::Error>> {
+        Config::load_with(options, |var| env::var(var).ok())
+    }
+
+    //Like load, with `env_var` in place of the process environment for finding the user file
+    //and the IMAGI_* overrides.
+    pub fn load_with(
+        options: &ConfigOptions,
+        env_var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut merged = toml::Table::new();
+        let mut origins = BTreeMap::new();
+
+        if let Some(path) = user_config_path(&env_var)
+            && path.is_file()
+        {
+            let table = read_table(&path)?;
+            merge_table(
+                &mut merged,
+                table,
+                "",
+                &Origin::UserFile(path),
+                &mut origins,
+            );
+        }
+
+        let project_file = match &options.config_file {
+            Some(path) if !path.is_file() => {
+                return Err(format!("Config file not found: {}", path.display()).into());
+            }
+            Some(path) => Some(path.clone()),
+            None => find_project_config(&env::current_dir()?),
+        };
+        if let Some(path) = project_file {
+            let table = read_table(&path)?;
+            merge_table(
+                &mut merged,
+                table,
+                "",
+                &Origin::ProjectFile(path),
+                &mut origins,
+            );
+        }
+
+        for (var, key) in ENV_OVERRIDES {
+            if let Some(value) = env_var(var) {
+                set_key(
+                    &mut merged,
+                    key,
+                    parse_value(&value),
+                    &Origin::Env(var),
+                    &mut origins,
+                );
+            }
+        }
+
+        for entry in &options.overrides {
+            let (key, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid --set '{}', expected KEY=VALUE", entry))?;
+            set_key(
+                &mut merged,
+                key.trim(),
+                parse_value(value.trim()),
+                &Origin::Cli,
+                &mut origins,
+            );
+        }
+
+        let mut config: Config = Value::Table(merged)
+            .try_into()
+            .map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.origins = origins;
+        config.anchor_relative_paths();
+        Ok(config)
+    }
+
+    //looks a task up by name or alias
+    pub fn find_task(&self, name: &str) -> Option<&TaskConfig> {
+        self.tasks.iter().find(|task| task.matches(name))
+    }
+
+    //directory with the unit tests of a task inside solutions.dir, None if no solutions dir is configured
+    pub fn task_tests_dir(&self, task: &TaskConfig) -> Option<PathBuf> {
+        let dir = self.solutions.dir.as_ref()?;
+        Some(dir.join(task.repo()).join(&task.tests_dir))
+    }
+
+    //where a key came from, None if it was left at its default
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        self.origins.get(key)
+    }
+
+    // Relative paths in a config file are relative to that file, not to wherever imagi was started.
+    fn anchor_relative_paths(&mut self) {
+        anchor(&self.origins, "java.jars_dir", &mut self.java.jars_dir);
+        if let Some(dir) = self.solutions.dir.as_mut() {
+            anchor(&self.origins, "solutions.dir", dir);
+        }
+        anchor(&self.origins, "usage.ledger", &mut self.usage.ledger);
+        anchor(&self.origins, "dry_run.outbox", &mut self.dry_run.outbox);
+        anchor(
+            &self.origins,
+            "privacy.key_file",
+            &mut self.privacy.key_file,
+        );
+        if let Some(roster) = self.privacy.roster.as_mut() {
+            anchor(&self.origins, "privacy.roster", roster);
+        }
+        if let Some(dir) = self.llm.prompt_dir.as_mut() {
+            anchor(&self.origins, "llm.prompt_dir", dir);
+        }
+        // a template file given in a config file, "student" and "teacher" are names
+        if !matches!(self.llm.prompt.as_str(), "student" | "teacher") {
+            let mut path = PathBuf::from(&self.llm.prompt);
+            anchor(&self.origins, "llm.prompt", &mut path);
+            self.llm.prompt = path.to_string_lossy().into_owned();
+        }
+    }
+}
+
+fn anchor(origins: &BTreeMap<String, Origin>, key: &str, path: &mut PathBuf) {
+    if path.is_absolute() {
+        return;
+    }
+    let file = match origins.get(key) {
+        Some(Origin::UserFile(file)) | Some(Origin::ProjectFile(file)) => file,
+        _ => return,
+    };
+    if let Some(dir) = file.parent() {
+        *path = dir.join(&*path);
+    }
+}
+
+// $XDG_DATA_HOME/imagi, like the user config lives in $XDG_CONFIG_HOME/imagi
+fn data_dir() -> PathBuf {
+    env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".local/share"))
+        })
+        .unwrap_or_default()
+        .join("imagi")
+}
+
+fn user_config_path(env_var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    if let Some(dir) = env_var("XDG_CONFIG_HOME")
+        && !dir.is_empty()
+    {
+        return Some(PathBuf::from(dir).join("imagi").join("config.toml"));
+    }
+    env_var("HOME").map(|home| PathBuf::from(home).join(".config/imagi/config.toml"))
+}
+
+fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|candidate| candidate.is_file())
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)?;
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|e| format!("Could not parse {}: {}", path.display(), e))?;
+    Ok(table)
+}
+
+// Values from env vars and --set are parsed as TOML when possible (numbers, bools, arrays),
+// anything else is taken as a plain string so `--set forge.student_org=inda-26` just works.
+fn parse_value(raw: &str) -> Value {
+    format!("v = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+// Deep-merges `layer` into `base`. Tables are merged key by key, everything else
+// (including arrays) replaces the lower layer and is recorded as coming from `origin`.
+fn merge_table(
+    base: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    for (key, value) in layer {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge_table(existing, table, &path, origin, origins);
+            }
+            (_, Value::Table(table)) => {
+                let mut fresh = toml::Table::new();
+                merge_table(&mut fresh, table, &path, origin, origins);
+                base.insert(key, Value::Table(fresh));
+            }
+            (_, value) => {
+                origins.retain(|existing, _| !existing.starts_with(&format!("{}.", path)));
+                origins.insert(path, origin.clone());
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// Sets a single dotted key, e.g. "forge.student_org".
+fn set_key(
+    base: &mut toml::Table,
+    key: &str,
+    value: Value,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap_or(key);
+    let mut nested = toml::Table::new();
+    nested.insert(last.to_string(), value);
+    while let Some(part) = parts.pop() {
+        let mut parent = toml::Table::new();
+        parent.insert(part.to_string(), Value::Table(nested));
+        nested = parent;
+    }
+    merge_table(base, nested, "", origin, origins);
+}
+
+fn flatten(value: &Value, prefix: &str, out: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(value, &path, out);
+            }
+        }
+        // arrays of tables (the task registry) get one line per entry
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_table) => {
+            for (index, item) in items.iter().enumerate() {
+                out.push((format!("{}[{}]", prefix, index), item.clone()));
+            }
+        }
+        _ => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+//Prints the effective config, one key per line, with the layer each value came from.
+pub fn show_config(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let value = Value::try_from(config)?;
+    let mut entries = Vec::new();
+    flatten(&value, "", &mut entries);
+
+    println!("\x1b[1;34mEffective configuration:\x1b[0m");
+    for (key, value) in entries {
+        let origin = config
+            .origin(key.split('[').next().unwrap_or(&key))
+            .map(|o| o.to_string())
+            .unwrap_or_else(|| "default".to_string());
+        println!("  {} = {}  \x1b[90m# {}\x1b[0m", key, value, origin);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // an environment with only these variables, the process environment is left alone
+    fn load_in(
+        vars: &[(&str, &str)],
+        options: ConfigOptions,
+    ) -> Result<Config, Box<dyn std::error::Error>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect();
+        Config::load_with(&options, |var| vars.get(var).cloned())
+    }
+
+    fn file_origin(config: &Config, key: &str) -> Option<PathBuf> {
+        match config.origin(key) {
+            Some(Origin::UserFile(path)) | Some(Origin::ProjectFile(path)) => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn layers_override_each_other_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_dir = dir.path().join("xdg");
+        let user_file = user_dir.join("imagi").join("config.toml");
+        fs::create_dir_all(user_file.parent().unwrap()).unwrap();
+        fs::write(
+            &user_file,
+            "[forge]\nhost = \"user.example\"\nstudent_org = \"user-org\"\nsolutions_org = \"user-sol\"\nrepo_pattern = \"user-{student}\"\n\n[java]\njars_dir = \"jars\"\n",
+        )
+        .unwrap();
+        let project_file = dir.path().join("course.toml");
+        fs::write(
+            &project_file,
+            "[forge]\nstudent_org = \"project-org\"\nsolutions_org = \"project-sol\"\nrepo_pattern = \"project-{student}\"\n",
+        )
+        .unwrap();
+
+        let config = load_in(
+            &[
+                ("XDG_CONFIG_HOME", user_dir.to_str().unwrap()),
+                ("IMAGI_SOLUTIONS_ORG", "env-sol"),
+                ("IMAGI_REPO_PATTERN", "env-{student}"),
+            ],
+            ConfigOptions {
+                config_file: Some(project_file.clone()),
+                overrides: vec![
+                    "forge.repo_pattern = cli-{student}-{task}".to_string(),
+                    "clone.workers=3".to_string(),
+                ],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(config.forge.host, "user.example");
+        assert_eq!(file_origin(&config, "forge.host"), Some(user_file.clone()));
+        assert_eq!(config.forge.student_org, "project-org");
+        assert_eq!(
+            file_origin(&config, "forge.student_org"),
+            Some(project_file)
+        );
+        assert_eq!(config.forge.solutions_org, "env-sol");
+        assert!(matches!(
+            config.origin("forge.solutions_org"),
+            Some(Origin::Env("IMAGI_SOLUTIONS_ORG"))
+        ));
+        assert_eq!(config.forge.repo_pattern, "cli-{student}-{task}");
+        assert!(matches!(
+            config.origin("forge.repo_pattern"),
+            Some(Origin::Cli)
+        ));
+        // --set values are TOML when they parse as TOML
+        assert_eq!(config.clone.workers, 3);
+        assert!(matches!(config.origin("clone.workers"), Some(Origin::Cli)));
+        // untouched keys keep their default and have no origin
+        assert_eq!(config.forge.api_base, ForgeConfig::default().api_base);
+        assert!(config.origin("forge.api_base").is_none());
+        // relative paths are relative to the file they are written in
+        assert_eq!(
+            config.java.jars_dir,
+            user_file.parent().unwrap().join("jars")
+        );
+    }
+
+    #[test]
+    fn bad_layers_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.toml");
+        fs::write(&empty, "").unwrap();
+        let load = |config_file: &Path, set: &str| {
+            load_in(
+                &[("XDG_CONFIG_HOME", dir.path().to_str().unwrap())],
+                ConfigOptions {
+                    config_file: Some(config_file.to_path_buf()),
+                    overrides: vec![set.to_string()],
+                },
+            )
+            .map(|_| ())
+            .unwrap_err()
+            .to_string()
+        };
+        let missing = load(&dir.path().join("nope.toml"), "clone.workers=1");
+        let no_value = load(&empty, "forge.host");
+        let typo = load(&empty, "forge.hots=x");
+        let broken = dir.path().join("broken.toml");
+        fs::write(&broken, "[forge\n").unwrap();
+        let unparsable = load(&broken, "clone.workers=1");
+
+        assert!(missing.contains("Config file not found"), "{}", missing);
+        assert!(no_value.contains("expected KEY=VALUE"), "{}", no_value);
+        assert!(typo.contains("hots"), "{}", typo);
+        assert!(unparsable.contains("broken.toml"), "{}", unparsable);
+    }
+
+    #[test]
+    fn the_nearest_project_file_is_found_in_a_parent_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("task-1").join("output");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join(PROJECT_CONFIG_FILE), "").unwrap();
+        assert_eq!(
+            find_project_config(&nested),
+            Some(dir.path().join(PROJECT_CONFIG_FILE))
+        );
+        let nearer = dir.path().join("task-1").join(PROJECT_CONFIG_FILE);
+        fs::write(&nearer, "").unwrap();
+        assert_eq!(find_project_config(&nested), Some(nearer));
+    }
+}
@@ -0,0 +1,203 @@
+use crate::grading::GradeStatus;
+use crate::review::ReviewItem;
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// Separates the header (status and comments) from the issue body in the edited file
+const SEPARATOR: &str = "---";
+
+//Opens the feedback in $VISUAL/$EDITOR as a markdown file and reads it back.
+//Returns the status and issue body, or None if the TA aborted: the editor failed
+//(e.g. :cq in vim) or the body was deleted. Saving without changes accepts the feedback as it is.
+pub fn edit_feedback(
+    item: &ReviewItem,
+) -> Result<Option<(GradeStatus, String)>, Box<dyn std::error::Error>> {
+    let editor = editor_command();
+    let file = tempfile::Builder::new()
+        .prefix(&format!("imagi-{}-", item.student_id))
+        .suffix(".md")
+        .tempfile()?;
+    let mut content = template(item);
+    loop {
+        fs::write(file.path(), &content)?;
+        // through sh so EDITOR can have arguments, e.g. "code --wait"
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(file.path())
+            .status()
+            .map_err(|e| format!("Could not start the editor '{}': {}", editor, e))?;
+        if !status.success() {
+            return Ok(None);
+        }
+        let edited = fs::read_to_string(file.path())?;
+        match parse_edited(&edited) {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                // Open the file again with the problem on top instead of throwing the edits away
+                println!("\x1b[1;31m❌ {}\x1b[0m", e);
+                content = format!("<!-- ERROR: {} -->\n{}", e, strip_error_lines(&edited));
+            }
+        }
+    }
+}
+
+// $VISUAL wins over $EDITOR like in git, vi is on every machine
+fn editor_command() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+//Status line, the test summary as a comment and the current issue body
+fn template(item: &ReviewItem) -> String {
+    // a "-->" in the test output would end the comment early
+    let summary = item.test_summary.trim().replace("-->", "-- >");
+    format!(
+        "Status: {}\n\
+         <!--\n\
+         Feedback for {} ({}). Change the status above to PASS, FAIL or KOMPLETTERING.\n\
+         Everything below the {} line is posted as the issue body.\n\
+         Save as it is to accept it. Delete the body or quit the editor with an error\n\
+         (e.g. :cq in vim) to skip this student.\n\
+         \n\
+         {}\n\
+         -->\n\
+         {}\n\
+         {}\n",
+        item.status,
+        item.student_id,
+        item.task,
+        SEPARATOR,
+        summary,
+        SEPARATOR,
+        item.issue_body().trim()
+    )
+}
+
+//Reads the status from the header and the body below the first --- line.
+//Ok(None) if the body is empty.
+fn parse_edited(text: &str) -> Result<Option<(GradeStatus, String)>, String> {
+    let (header, body) = split_at_separator(text).ok_or_else(|| {
+        format!(
+            "the {} line between the status and the body is missing",
+            SEPARATOR
+        )
+    })?;
+    let body = body.trim();
+    if body.is_empty() {
+        return Ok(None);
+    }
+    let header = strip_comments(header);
+    let status_line = header
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .ok_or("the Status: line is missing")?;
+    let value = status_line
+        .split_once(':')
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case("status"))
+        .map(|(_, value)| value)
+        .ok_or_else(|| format!("expected 'Status: <status>', got '{}'", status_line))?;
+    let status: GradeStatus = value.parse()?;
+    if !status.is_graded() {
+        return Err(format!("{} can not be posted", status));
+    }
+    Ok(Some((status, body.to_string())))
+}
+
+// The first line outside a comment that is exactly the separator. The body can contain
+// its own --- lines, the test summary in the header comment too.
+fn split_at_separator(text: &str) -> Option<(&str, &str)> {
+    let mut offset = 0;
+    let mut in_comment = false;
+    for line in text.split_inclusive('\n') {
+        if !in_comment && line.trim_end() == SEPARATOR {
+            return Some((&text[..offset], &text[offset + line.len()..]));
+        }
+        if let Some(open) = line.rfind("<!--") {
+            in_comment = !line[open..].contains("-->");
+        } else if line.contains("-->") {
+            in_comment = false;
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<!--") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start..].find("-->") {
+            Some(end) => &rest[start + end + 3..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+fn strip_error_lines(text: &str) -> String {
+    text.lines()
+        .filter(|line| !line.starts_with("<!-- ERROR: "))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_and_body_are_read() {
+        let text = "Status: komp\n<!--\nFeedback for alice\n---\n-->\n---\nFix the loop.\n\n---\nThanks!\n";
+        let (status, body) = parse_edited(text).unwrap().unwrap();
+        assert_eq!(status, GradeStatus::Komplettering);
+        // the --- in the comment is skipped, the one in the body is kept
+        assert_eq!(body, "Fix the loop.\n\n---\nThanks!");
+    }
+
+    #[test]
+    fn saving_without_changes_accepts_the_feedback() {
+        let item: ReviewItem = serde_json::from_value(serde_json::json!({
+            "student_id": "alice",
+            "task": "task-1",
+            "state": "pending",
+            "status": "FAIL",
+            "ai_status": "FAIL",
+            "ai_feedback": "The loop in Deck.shuffle never ends.\n\n---\nCheck the bounds.",
+            "confidence": 0.8,
+            "test_summary": "Outcome: 2 of 3 tests passed\n---",
+            "updated_at": "2025-10-17T12:00:00+02:00",
+        }))
+        .unwrap();
+        let (status, body) = parse_edited(&template(&item)).unwrap().unwrap();
+        assert_eq!(status, GradeStatus::Fail);
+        assert_eq!(body, item.issue_body().trim());
+    }
+
+    #[test]
+    fn an_empty_body_skips_the_student() {
+        assert_eq!(parse_edited("Status: PASS\n---\n\n  \n").unwrap(), None);
+    }
+
+    #[test]
+    fn a_missing_separator_is_an_error() {
+        let err = parse_edited("Status: PASS\nGood job\n").unwrap_err();
+        assert!(err.contains("---"), "{}", err);
+    }
+
+    #[test]
+    fn the_status_line_is_checked() {
+        assert!(parse_edited("---\nbody\n").is_err());
+        assert!(parse_edited("State: PASS\n---\nbody\n").is_err());
+        assert!(parse_edited("Status: MAYBE\n---\nbody\n").is_err());
+        assert!(parse_edited("Status: NOT_GRADED\n---\nbody\n").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use crate::config::UpdateMode;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    Commit, Cred, CredentialType, Direction, FetchOptions, Progress, Remote, RemoteCallbacks,
+    Repository, ResetType, Sort,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+//one repository to clone
+pub struct CloneJob {
+    // what the job is reported as, e.g. "alice-task-5"
+    pub name: String,
+    pub url: String,
+    pub dest: PathBuf,
+    // branch to check out, None for the remote default branch
+    pub branch: Option<String>,
+}
+
+pub enum CloneOutcome {
+    Cloned { head: String },
+    // an existing checkout was fetched and moved to the remote branch, before == after if nothing new
+    Updated { before: String, after: String },
+    Failed(String),
+}
+
+pub struct CloneResult {
+    pub job: CloneJob,
+    pub outcome: CloneOutcome,
+}
+
+impl CloneResult {
+    //true if there is a usable checkout at job.dest
+    pub fn is_ok(&self) -> bool {
+        !matches!(self.outcome, CloneOutcome::Failed(_))
+    }
+
+    //commit the checkout is at after cloning/updating
+    pub fn head(&self) -> Option<&str> {
+        match &self.outcome {
+            CloneOutcome::Cloned { head } => Some(head),
+            CloneOutcome::Updated { after, .. } => Some(after),
+            CloneOutcome::Failed(_) => None,
+        }
+    }
+}
+
+//Callbacks used for every network operation: SSH agent credentials plus optional progress output.
+fn remote_callbacks<'a>(label: &'a str) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut attempts = 0;
+    callbacks.credentials(move |_url, username_from_url, allowed| {
+        // libgit2 keeps asking as long as we hand out credentials, so give up after the first rejection
+        attempts += 1;
+        if attempts > 1 {
+            return Err(git2::Error::from_str(
+                "SSH authentication failed, is your key added to ssh-agent?",
+            ));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username_from_url.unwrap_or("git"))
+        } else {
+            Err(git2::Error::from_str(
+                "only SSH remotes are supported (git@host:org/repo.git)",
+            ))
+        }
+    });
+
+    let mut last_quarter = 0;
+    callbacks.transfer_progress(move |stats: Progress| {
+        let total = stats.total_objects();
+        if let Some(quarter) = (stats.received_objects() * 4).checked_div(total)
+            && quarter > last_quarter
+        {
+            last_quarter = quarter;
+            println!(
+                "   \x1b[90m{}: {}% ({}/{} objects)\x1b[0m",
+                label,
+                quarter * 25,
+                stats.received_objects(),
+                total
+            );
+        }
+        true
+    });
+    callbacks
+}
+
+fn fetch_options(label: &str) -> FetchOptions<'_> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(remote_callbacks(label));
+    options
+}
+
+fn head_sha(repo: &Repository) -> Result<String, git2::Error> {
+    Ok(repo.head()?.peel_to_commit()?.id().to_string())
+}
+
+//Clones a single repo with git2, or updates it if there already is a checkout at the destination.
+pub fn clone_repo(job: &CloneJob, mode: UpdateMode) -> CloneOutcome {
+    if job.dest.exists() {
+        return match update_repo(job, mode) {
+            Ok((before, after)) => CloneOutcome::Updated { before, after },
+            Err(e) => CloneOutcome::Failed(format!("update failed: {}", e.message())),
+        };
+    }
+    let mut builder = RepoBuilder::new();
+    builder.fetch_options(fetch_options(&job.name));
+    if let Some(branch) = &job.branch {
+        builder.branch(branch);
+    }
+    match builder
+        .clone(&job.url, &job.dest)
+        .and_then(|repo| head_sha(&repo))
+    {
+        Ok(head) => CloneOutcome::Cloned { head },
+        Err(e) => {
+            // libgit2 can leave a half-written directory behind, which would look like an existing clone next time
+            let _ = std::fs::remove_dir_all(&job.dest);
+            CloneOutcome::Failed(e.message().to_string())
+        }
+    }
+}
+
+// Asks the remote which branch HEAD points at, e.g. "main".
+fn remote_default_branch(remote: &mut Remote, label: &str) -> Result<String, git2::Error> {
+    let connection = remote.connect_auth(Direction::Fetch, Some(remote_callbacks(label)), None)?;
+    let name = connection.default_branch()?;
+    let name = name
+        .as_str()
+        .ok_or_else(|| git2::Error::from_str("remote default branch is not valid UTF-8"))?;
+    Ok(name.trim_start_matches("refs/heads/").to_string())
+}
+
+// Fetches origin and moves the local branch (and the work tree) to the remote branch.
+// Returns the commit the local branch was at before (HEAD if there is no local branch) and after.
+fn update_repo(job: &CloneJob, mode: UpdateMode) -> Result<(String, String), git2::Error> {
+    let repo = Repository::open(&job.dest)?;
+    let mut remote = repo.find_remote("origin")?;
+    let branch = match &job.branch {
+        Some(branch) => branch.clone(),
+        None => remote_default_branch(&mut remote, &job.name)?,
+    };
+    let refspec = format!("+refs/heads/{0}:refs/remotes/origin/{0}", branch);
+    remote.fetch(&[refspec], Some(&mut fetch_options(&job.name)), None)?;
+
+    let target = repo
+        .find_reference(&format!("refs/remotes/origin/{}", branch))?
+        .peel_to_commit()?;
+    let local_ref = format!("refs/heads/{}", branch);
+    // HEAD is detached at an older commit after checkout_before_deadline, the branch is what moves
+    let before = match repo.find_reference(&local_ref) {
+        Ok(local) => local.peel_to_commit()?.id().to_string(),
+        Err(_) => head_sha(&repo)?,
+    };
+    if mode == UpdateMode::FastForward
+        && let Ok(local) = repo.find_reference(&local_ref)
+    {
+        let local = local.peel_to_commit()?;
+        if local.id() != target.id() && !repo.graph_descendant_of(target.id(), local.id())? {
+            return Err(git2::Error::from_str(&format!(
+                "local {} has diverged from origin/{}, set clone.update = \"reset\" to discard local commits",
+                branch, branch
+            )));
+        }
+    }
+    repo.reference(&local_ref, target.id(), true, "imagi: update from origin")?;
+    repo.set_head(&local_ref)?;
+    match mode {
+        UpdateMode::FastForward => repo.checkout_head(Some(CheckoutBuilder::new().force()))?,
+        UpdateMode::Reset => repo.reset(target.as_object(), ResetType::Hard, None)?,
+    }
+    Ok((before, target.id().to_string()))
+}
+
+//Clones (or updates) all jobs on a pool of `workers` threads. Results come back in the same order as the jobs.
+pub fn clone_all(jobs: Vec<CloneJob>, workers: usize, mode: UpdateMode) -> Vec<CloneResult> {
+    let total = jobs.len();
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<CloneOutcome>>> = Mutex::new((0..total).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, total.max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = jobs.get(index) else {
+                        break;
+                    };
+                    let outcome = clone_repo(job, mode);
+                    let finished = done.fetch_add(1, Ordering::SeqCst) + 1;
+                    match &outcome {
+                        CloneOutcome::Cloned { .. } => {
+                            println!("[{}/{}] ✅ {} cloned", finished, total, job.name)
+                        }
+                        CloneOutcome::Updated { before, after } if before == after => {
+                            println!(
+                                "[{}/{}] ⏭️ {} already up to date",
+                                finished, total, job.name
+                            )
+                        }
+                        CloneOutcome::Updated { before, after } => println!(
+                            "[{}/{}] 🔄 {} updated {} -> {}",
+                            finished,
+                            total,
+                            job.name,
+                            short_sha(before),
+                            short_sha(after)
+                        ),
+                        CloneOutcome::Failed(reason) => {
+                            println!(
+                                "[{}/{}] ❌ {} failed: {}",
+                                finished, total, job.name, reason
+                            )
+                        }
+                    }
+                    outcomes.lock().unwrap()[index] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    let outcomes = outcomes.into_inner().unwrap();
+    jobs.into_iter()
+        .zip(outcomes)
+        .map(|(job, outcome)| CloneResult {
+            job,
+            outcome: outcome.unwrap_or_else(|| CloneOutcome::Failed("not attempted".to_string())),
+        })
+        .collect()
+}
+
+//commit picked for grading by checkout_before_deadline
+pub struct Snapshot {
+    pub commit: String,
+    pub time: DateTime<FixedOffset>,
+    // commits on the branch that were made after the deadline and are not graded
+    pub late_commits: usize,
+}
+
+fn commit_time(commit: &Commit) -> Option<DateTime<FixedOffset>> {
+    let time = commit.time();
+    FixedOffset::east_opt(time.offset_minutes() * 60)?
+        .timestamp_opt(time.seconds(), 0)
+        .single()
+}
+
+//Checks out (detached) the newest commit on HEAD's history that was committed before the deadline,
+//None if there is none. The committer date is used since it is set when the commit is made/rebased,
+//which is the closest thing to a push time that git records. The walk is topological, so a commit
+//is never picked over a later commit built on it, whatever their dates say.
+pub fn checkout_before_deadline(
+    repo_dir: &Path,
+    deadline: DateTime<FixedOffset>,
+) -> Result<Option<Snapshot>, git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+    let mut late_commits = 0;
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let time = commit_time(&commit)
+            .ok_or_else(|| git2::Error::from_str("commit has an invalid timestamp"))?;
+        if time > deadline {
+            late_commits += 1;
+            continue;
+        }
+        repo.set_head_detached(commit.id())?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+        return Ok(Some(Snapshot {
+            commit: commit.id().to_string(),
+            time,
+            late_commits,
+        }));
+    }
+    Ok(None)
+}
+
+pub fn short_sha(sha: &str) -> &str {
+    &sha[..sha.len().min(8)]
+}
+
+//Prints which repos were cloned, updated, already up to date and which failed (with the reason).
+pub fn print_clone_summary(results: &[CloneResult]) {
+    let cloned: Vec<&CloneResult> = results
+        .iter()
+        .filter(|r| matches!(r.outcome, CloneOutcome::Cloned { .. }))
+        .collect();
+    let updated: Vec<(&CloneResult, &String, &String)> = results
+        .iter()
+        .filter_map(|r| match &r.outcome {
+            CloneOutcome::Updated { before, after } if before != after => Some((r, before, after)),
+            _ => None,
+        })
+        .collect();
+    let existing: Vec<&CloneResult> = results
+        .iter()
+        .filter(
+            |r| matches!(&r.outcome, CloneOutcome::Updated { before, after } if before == after),
+        )
+        .collect();
+    let failed: Vec<(&CloneResult, &String)> = results
+        .iter()
+        .filter_map(|r| match &r.outcome {
+            CloneOutcome::Failed(reason) => Some((r, reason)),
+            _ => None,
+        })
+        .collect();
+
+    println!("\n{}", "=".repeat(60));
+    println!(
+        "\x1b[1;34mClone summary:\x1b[0m {} cloned, {} updated, {} already up to date, {} failed",
+        cloned.len(),
+        updated.len(),
+        existing.len(),
+        failed.len()
+    );
+    if !cloned.is_empty() {
+        println!("\x1b[1;32m✅ Cloned:\x1b[0m");
+        for result in &cloned {
+            println!("   {}", result.job.name);
+        }
+    }
+    if !updated.is_empty() {
+        println!("\x1b[1;36m🔄 Updated:\x1b[0m");
+        for (result, before, after) in &updated {
+            println!(
+                "   {}: {} -> {}",
+                result.job.name,
+                short_sha(before),
+                short_sha(after)
+            );
+        }
+    }
+    if !existing.is_empty() {
+        println!("\x1b[1;33m⏭️ Already up to date:\x1b[0m");
+        for result in &existing {
+            println!("   {}", result.job.name);
+        }
+    }
+    if !failed.is_empty() {
+        println!("\x1b[1;31m❌ Failed:\x1b[0m");
+        for (result, reason) in &failed {
+            println!("   {}: {}", result.job.name, reason);
+        }
+    }
+    println!("{}", "=".repeat(60));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Signature, Time};
+
+    // commits an empty tree to main in `repo` at `seconds` since the epoch
+    fn commit_at(repo: &Repository, seconds: i64) -> String {
+        let head = repo
+            .head()
+            .ok()
+            .map(|head| head.target().unwrap().to_string());
+        let commit = commit_on(repo, seconds, head.as_slice());
+        repo.reference("refs/heads/main", commit.parse().unwrap(), true, "test")
+            .unwrap();
+        commit
+    }
+
+    // a commit with the given parents that no branch points at
+    fn commit_on(repo: &Repository, seconds: i64, parents: &[String]) -> String {
+        let signature = Signature::new("Student", "s@example.com", &Time::new(seconds, 0)).unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let parents: Vec<Commit> = parents
+            .iter()
+            .map(|sha| repo.find_commit(sha.parse().unwrap()).unwrap())
+            .collect();
+        let parents: Vec<&Commit> = parents.iter().collect();
+        repo.commit(None, &signature, &signature, "work", &tree, &parents)
+            .unwrap()
+            .to_string()
+    }
+
+    fn deadline(seconds: i64) -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(0)
+            .unwrap()
+            .timestamp_opt(seconds, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn a_rerun_on_a_deadline_snapshot_is_up_to_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = Repository::init(dir.path().join("origin")).unwrap();
+        origin.set_head("refs/heads/main").unwrap();
+        let first = commit_at(&origin, 1_000);
+        let second = commit_at(&origin, 2_000);
+        let late = commit_at(&origin, 3_000);
+        let job = CloneJob {
+            name: "alice-task-1".to_string(),
+            url: dir.path().join("origin").display().to_string(),
+            dest: dir.path().join("alice-task-1"),
+            branch: Some("main".to_string()),
+        };
+
+        assert!(matches!(
+            clone_repo(&job, UpdateMode::FastForward),
+            CloneOutcome::Cloned { head } if head == late
+        ));
+        let snapshot = checkout_before_deadline(&job.dest, deadline(2_500))
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.commit, second);
+        assert_eq!(snapshot.late_commits, 1);
+
+        // nothing was pushed, HEAD being detached at the snapshot is not an update
+        match clone_repo(&job, UpdateMode::FastForward) {
+            CloneOutcome::Updated { before, after } => {
+                assert_eq!(before, late);
+                assert_eq!(after, late);
+            }
+            _ => panic!("expected an update"),
+        }
+        let snapshot = checkout_before_deadline(&job.dest, deadline(1_500))
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.commit, first);
+        assert_eq!(snapshot.late_commits, 2);
+        assert!(
+            checkout_before_deadline(&job.dest, deadline(500))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn merged_histories_and_skewed_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        // the template, and an orphan branch with work started after the deadline
+        let template = commit_on(&repo, 1_000, &[]);
+        let orphan = commit_on(&repo, 3_000, &[]);
+        let merge = commit_on(&repo, 3_500, &[template.clone(), orphan]);
+        // a later commit whose clock was behind, its committer date is before the deadline
+        let skewed = commit_on(&repo, 1_800, &[merge]);
+        let tip = commit_on(&repo, 4_000, std::slice::from_ref(&skewed));
+        repo.reference("refs/heads/main", tip.parse().unwrap(), true, "test")
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+
+        // the newest commit before the deadline, even though it comes after late ones
+        let snapshot = checkout_before_deadline(dir.path(), deadline(2_000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.commit, skewed);
+        assert_eq!(snapshot.late_commits, 1);
+
+        repo.set_head("refs/heads/main").unwrap();
+        let snapshot = checkout_before_deadline(dir.path(), deadline(1_500))
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.commit, template);
+        assert_eq!(snapshot.late_commits, 4);
+    }
+}
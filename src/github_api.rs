@@ -1,148 +1,369 @@
+use crate::budget::truncation_lines;
+use crate::cache::{cache_key, load_cached, store_cached};
+use crate::config::{Config, TaskConfig};
+use crate::git_ops::{
+    CloneJob, CloneOutcome, checkout_before_deadline, clone_all, print_clone_summary, short_sha,
+};
+use crate::grading::{GradeResponse, GradeStatus, grade, referable_files};
+use crate::http::http_client;
+use crate::json_parser::FailedStudent;
 use crate::json_parser::IssueTitle;
+use crate::json_parser::Payload;
 use crate::json_parser::SourceFile;
 use crate::json_parser::StatusIssue;
+use crate::json_parser::SubmissionEntry;
 use crate::json_parser::create_feedback_json;
 use crate::json_parser::create_issue;
-use crate::json_parser::create_payload_json;
 use crate::json_parser::parse_issue_status;
 use crate::json_parser::parse_source_file;
-use reqwest::Client;
+use crate::llm::{LlmProvider, prompt_budget, provider_from_config};
+use crate::outbox::record_request;
+use crate::process::run_limited;
+use crate::prompt::build_prompt;
+use crate::pseudonym::Pseudonyms;
+use crate::review::{
+    Decision, PostPolicy, ReviewItem, ReviewState, print_review_item, review_dir, review_item,
+    review_item_path,
+};
+use crate::roster::read_roster;
+use crate::test_report::{
+    TestOutcome, TestReport, find_test_methods, is_out_of_memory, parse_junit_output,
+};
+use crate::usage::UsageSession;
+use chrono::{DateTime, FixedOffset};
 use reqwest::header::AUTHORIZATION;
 use reqwest::header::HeaderMap;
 use reqwest::header::HeaderValue;
 use reqwest::header::USER_AGENT;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
-use std::fs::File;
-use std::io::{BufRead, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 
 //clones students repos and creates json files with paths to their src dirs.
+//With a deadline (or a per-student extension in the roster) the last commit before it is checked out.
 pub fn clone_repos(
+    config: &Config,
     students: PathBuf,
     task: String,
     output_dir: PathBuf,
+    deadline: Option<DateTime<FixedOffset>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Get current working directory
-    let file = File::open(students)?;
-    let buf = std::io::BufReader::new(file);
-    let mut students_list = Vec::new();
-    for line in buf.lines() {
-        let student = line?.trim().to_string();
-        if student.is_empty() || student.starts_with('#') {
-            continue; // Skip empty lines and comments
-        }
-        students_list.push(student);
-    }
-    let mut map: HashMap<String, PathBuf> = HashMap::new();
+    let roster = read_roster(&students)?;
     // Create ./task directory
     let repos_dir = output_dir.join(&task);
     std::fs::create_dir_all(&repos_dir)?;
-    let base_url = "git@gits-15.sys.kth.se:inda-25/";
-    for student in students_list {
-        // Build repo URL and destination directory
-        let student_url = format!("{}{}-{}.git", base_url, student, task);
-        let repo_name = format!("{}-{}", student, task);
-        let student_dir = repos_dir.join(&repo_name);
-        Command::new("git")
-            .arg("clone")
-            .arg(&student_url)
-            .arg(&student_dir)
-            .status()?;
-        let src_dir = student_dir.join("src");
-        map.insert(student, src_dir);
+    let json_path = repos_dir.join("src_paths.json");
+    let previous = read_src_paths(&json_path).unwrap_or_default();
+    let mut map: BTreeMap<String, SubmissionEntry> = BTreeMap::new();
+    let jobs = roster
+        .iter()
+        .map(|entry| {
+            // Build repo URL and destination directory
+            let repo_name = config.forge.repo_name(&entry.student, &task);
+            CloneJob {
+                url: config.forge.student_clone_url(&entry.student, &task),
+                dest: repos_dir.join(&repo_name),
+                name: repo_name,
+                branch: None,
+            }
+        })
+        .collect();
+    let mut results = clone_all(jobs, config.clone.workers, config.clone.update);
+    // Only repos that are actually on disk end up in src_paths.json
+    let mut late = Vec::new();
+    let mut no_snapshot = Vec::new();
+    for (roster_entry, result) in roster.into_iter().zip(&mut results) {
+        if !result.is_ok() {
+            continue;
+        }
+        let student = roster_entry.student;
+        let mut entry = SubmissionEntry {
+            src: result.job.dest.join("src"),
+            commit: result.head().map(|sha| sha.to_string()),
+            commit_time: None,
+            previous_commit: previous
+                .get(&student)
+                .and_then(|entry| entry.commit.clone()),
+            changed: true,
+            deadline: None,
+            late_commits: 0,
+        };
+        if let Some(deadline) = roster_entry.deadline.or(deadline) {
+            match checkout_before_deadline(&result.job.dest, deadline) {
+                Ok(Some(snapshot)) => {
+                    // a deadline run is up to date if the snapshot did not move, whatever the branch did
+                    if let CloneOutcome::Updated { before, after } = &mut result.outcome {
+                        if let Some(previous) = &entry.previous_commit {
+                            *before = previous.clone();
+                        }
+                        *after = snapshot.commit.clone();
+                    }
+                    entry.commit = Some(snapshot.commit);
+                    entry.commit_time = Some(snapshot.time.to_rfc3339());
+                    entry.deadline = Some(deadline.to_rfc3339());
+                    entry.late_commits = snapshot.late_commits;
+                }
+                Ok(None) => {
+                    late.push(student);
+                    continue;
+                }
+                Err(e) => {
+                    no_snapshot.push((student, e.message().to_string()));
+                    continue;
+                }
+            }
+        }
+        entry.changed = entry.previous_commit.is_none() || entry.previous_commit != entry.commit;
+        map.insert(student, entry);
+    }
+    print_clone_summary(&results);
+
+    if map.values().any(|entry| entry.deadline.is_some())
+        || !late.is_empty()
+        || !no_snapshot.is_empty()
+    {
+        print_deadline_summary(&map, &late, &no_snapshot);
     }
+
+    let changed: Vec<&String> = map
+        .iter()
+        .filter(|(_, entry)| entry.changed)
+        .map(|(student, _)| student)
+        .collect();
+    if !previous.is_empty() {
+        println!(
+            "\x1b[1;36m🔁 Changed since the last run ({}/{}):\x1b[0m",
+            changed.len(),
+            map.len()
+        );
+        for student in &changed {
+            println!("   {}", student);
+        }
+    }
+
     let json_string = serde_json::to_string_pretty(&map)?;
-    let json_path = repos_dir.join("src_paths.json");
     std::fs::write(json_path, json_string)?;
     Ok(())
 }
 
+// Lists the students who are late or whose post-deadline commits were left out.
+fn print_deadline_summary(
+    map: &BTreeMap<String, SubmissionEntry>,
+    late: &[String],
+    no_snapshot: &[(String, String)],
+) {
+    let ignored: Vec<(&String, &SubmissionEntry)> = map
+        .iter()
+        .filter(|(_, entry)| entry.late_commits > 0)
+        .collect();
+
+    println!("\x1b[1;34m⏰ Deadline summary:\x1b[0m");
+    if !late.is_empty() {
+        println!(
+            "\x1b[1;31m   Late, no commit before the deadline (left out of src_paths.json):\x1b[0m"
+        );
+        for student in late {
+            println!("      {}", student);
+        }
+    }
+    if !ignored.is_empty() {
+        println!("\x1b[1;33m   Commits after the deadline ignored:\x1b[0m");
+        for (student, entry) in ignored {
+            println!(
+                "      {}: {} late commit(s), grading {}",
+                student,
+                entry.late_commits,
+                entry.commit.as_deref().map(short_sha).unwrap_or("?")
+            );
+        }
+    }
+    if !no_snapshot.is_empty() {
+        println!("\x1b[1;31m   No gradable commit (left out of src_paths.json):\x1b[0m");
+        for (student, reason) in no_snapshot {
+            println!("      {}: {}", student, reason);
+        }
+    }
+}
+
+//Reads src_paths.json written by clone_repos.
+pub fn read_src_paths(
+    json_path: &Path,
+) -> Result<BTreeMap<String, SubmissionEntry>, Box<dyn std::error::Error>> {
+    let json_string = fs::read_to_string(json_path)?;
+    let map = serde_json::from_str(&json_string)?;
+    Ok(map)
+}
+
 //Function to transform student's task/homework into format for JSON parsing.
 //Gets called when we create payload for api.
+//Subdirectories (packages) are included, names are relative to repo_dir, e.g. model/Card.java
 pub fn transform_contents(
     repo_dir: &Path,
 ) -> Result<(Vec<PathBuf>, Vec<String>), Box<dyn std::error::Error>> {
     let mut files = Vec::new();
     let mut names = Vec::new();
-    for file in fs::read_dir(repo_dir)? {
-        let file = file?;
-        let file_path = file.path();
-        if file_path.is_file() {
-            if let Some(name) = file_path.file_name().and_then(|n| n.to_str()) {
-                if !name.contains("Test") && name.contains("java") {
-                    names.push(name.to_string());
-                    files.push(file_path);
+    for relative in walk_files(repo_dir)? {
+        if let Some(name) = relative.file_name().and_then(|n| n.to_str())
+            && !name.contains("Test")
+            && name.ends_with(".java")
+        {
+            names.push(relative.to_string_lossy().into_owned());
+            files.push(repo_dir.join(relative));
+        }
+    }
+    Ok((files, names))
+}
+
+//The *Test.java files in the student's repo, often the starter tests. They go into the payload
+//separately so the prompt can tell them apart from the graded code.
+pub fn student_test_files(repo_dir: &Path) -> Result<Vec<SourceFile>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for relative in walk_files(repo_dir)? {
+        if let Some(name) = relative.file_name().and_then(|n| n.to_str())
+            && name.contains("Test")
+            && name.ends_with(".java")
+        {
+            files.push(parse_source_file(
+                &relative.to_string_lossy(),
+                &repo_dir.join(&relative),
+            )?);
+        }
+    }
+    Ok(files)
+}
+
+//All files below dir as paths relative to dir, sorted. Hidden directories are skipped.
+fn walk_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    fn walk(
+        root: &Path,
+        relative: &Path,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for entry in fs::read_dir(root.join(relative))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let path = relative.join(&name);
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if !name.to_string_lossy().starts_with('.') {
+                    walk(root, &path, files)?;
                 }
+            } else if file_type.is_file() {
+                files.push(path);
             }
         }
+        Ok(())
     }
-    Ok((files, names))
+    let mut files = Vec::new();
+    walk(dir, Path::new(""), &mut files)?;
+    files.sort();
+    Ok(files)
 }
 
-//Clones tests for tasks from inda-master org.
-pub fn get_tests(output_dir: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    let basic_url = "git@gits-15.sys.kth.se:inda-master/{task}.git";
-
-    for task_num in 1..=18 {
-        let task = format!("task-{}", task_num);
-        let repo_url = basic_url.replace("{task}", &task);
-        let dest_dir = output_dir.join(&task);
-        println!("Cloning {} into {:?}", repo_url, dest_dir);
-        Command::new("git")
-            .arg("clone")
-            .arg(&repo_url)
-            .arg(&dest_dir)
-            .status()?;
-
-        Command::new("git")
-            .arg("-C")
-            .arg(&dest_dir)
-            .arg("checkout")
-            .arg("-B")
-            .arg("solutions")
-            .arg("origin/solutions")
-            .status()?;
-    }
-
-    let quicksort = "quicksort";
-    let repo_url = basic_url.replace("{task}", &quicksort);
-    let dest_dir = output_dir.join(&quicksort);
-    println!("Cloning {} into {:?}", repo_url, dest_dir);
-    Command::new("git")
-        .arg("clone")
-        .arg(&repo_url)
-        .arg(&dest_dir)
-        .status()?;
-
-    Command::new("git")
-        .arg("-C")
-        .arg(&dest_dir)
-        .arg("checkout")
-        .arg("-B")
-        .arg("solutions")
-        .arg("origin/solutions")
-        .status()?;
+//copies a file to dest_root/relative, creating the package directories on the way
+fn copy_relative(
+    src_root: &Path,
+    relative: &Path,
+    dest_root: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dest = dest_root.join(relative);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(src_root.join(relative), dest)?;
+    Ok(())
+}
+
+//Clones (or updates) the solution repos of the task registry from the solutions org.
+//An empty selection means every task in the registry.
+pub fn get_tests(
+    config: &Config,
+    output_dir: PathBuf,
+    selected: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tasks: Vec<&TaskConfig> = if selected.is_empty() {
+        config.tasks.iter().collect()
+    } else {
+        selected
+            .iter()
+            .map(|name| find_task(config, name))
+            .collect::<Result<_, _>>()?
+    };
+
+    let jobs = tasks
+        .into_iter()
+        .map(|task| CloneJob {
+            url: config.forge.solutions_clone_url(task.repo()),
+            dest: output_dir.join(task.repo()),
+            name: task.name.clone(),
+            branch: Some(task.branch.clone()),
+        })
+        .collect();
+    let results = clone_all(jobs, config.clone.workers, config.clone.update);
+    print_clone_summary(&results);
 
     Ok(())
 }
 
+fn find_task<'a>(
+    config: &'a Config,
+    name: &str,
+) -> Result<&'a TaskConfig, Box<dyn std::error::Error>> {
+    config.find_task(name).ok_or_else(|| {
+        let known: Vec<&str> = config.tasks.iter().map(|t| t.name.as_str()).collect();
+        format!("Unknown task '{}'. Known tasks: {}", name, known.join(", ")).into()
+    })
+}
+
+//Finds the unit tests for a task in the solutions dir, for when `clone` is run without --unittest.
+pub fn resolve_unittest_dir(
+    config: &Config,
+    task: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let task = find_task(config, task)?;
+    let dir = config.task_tests_dir(task).ok_or(
+        "No --unittest given and solutions.dir is not configured. Set it in imagi.toml or pass -u.",
+    )?;
+    if !dir.is_dir() {
+        return Err(format!(
+            "Unit tests for {} not found in {}. Run `imagi tests --task {}` first.",
+            task.name,
+            dir.display(),
+            task.name
+        )
+        .into());
+    }
+    Ok(dir)
+}
+
 //creating payload from repo
-pub fn create_payload(
+//with changed_only, students whose commit is the same as in the previous clone run are skipped
+//with keep_build, the temporary build directories are left on disk for debugging
+//up to `jobs` students are compiled and tested at the same time, the status lines are still
+//printed in student order
+pub async fn create_payload(
+    config: &Config,
     students_repo: PathBuf,
     path_to_task_dir: PathBuf,
     tests_dir: PathBuf,
+    changed_only: bool,
+    keep_build: bool,
+    jobs: usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let json_string = fs::read_to_string(students_repo)?;
     let mut readme = String::new();
     let mut task = String::new();
-    let map: HashMap<String, PathBuf> = serde_json::from_str(&json_string)?;
+    let map: BTreeMap<String, SubmissionEntry> = read_src_paths(&students_repo)?
+        .into_iter()
+        .filter(|(_, entry)| entry.changed || !changed_only)
+        .collect();
     if let Some(val) = map.values().next() {
-        let mut readme_path = val.clone(); // val: &PathBuf
+        let mut readme_path = val.src.clone(); // val.src: PathBuf
         readme_path.pop(); // removes "src"
         readme_path.push("README.md");
         readme = std::fs::read_to_string(&readme_path)?;
@@ -158,207 +379,373 @@ pub fn create_payload(
     std::fs::create_dir_all(&dir_path)?;
 
     let total_students = map.len();
-    let mut processed = 0;
+    let started = Instant::now();
+    let limit = Arc::new(Semaphore::new(jobs.max(1)));
+    let job = Arc::new(StudentJob {
+        config: config.clone(),
+        task,
+        readme,
+        tests_dir,
+        dir_path,
+        keep_build,
+    });
+
+    // Spawn everything up front, the semaphore decides how many run at once
+    let mut handles = Vec::new();
+    for (key, value) in map {
+        let limit = Arc::clone(&limit);
+        let job = Arc::clone(&job);
+        handles.push((
+            key.clone(),
+            tokio::spawn(async move {
+                let _permit = limit.acquire_owned().await;
+                tokio::task::spawn_blocking(move || job.run(&key, &value)).await
+            }),
+        ));
+    }
 
-    for (key, value) in &map {
-        processed += 1;
+    let mut summary = Vec::new();
+    for (processed, (key, handle)) in handles.into_iter().enumerate() {
+        let result = match handle.await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) | Err(e) => Err(format!("worker failed: {}", e)),
+        };
         print!(
             "Processing student {} ({}/{})... ",
-            key, processed, total_students
+            key,
+            processed + 1,
+            total_students
         );
-        std::io::stdout().flush().unwrap();
+        match &result {
+            Ok(run) => {
+                println!("{}", run.report.status_line());
+                if let Some(kept) = &run.kept_build {
+                    println!("   📁 Build directory kept at {}", kept.display());
+                }
+            }
+            Err(e) => println!("❌ ERROR: {}", e),
+        }
+        summary.push((key, result));
+    }
 
+    print_run_summary(&summary, started.elapsed());
+    Ok(())
+}
+
+//what every compile/test worker needs, shared between the tasks
+struct StudentJob {
+    config: Config,
+    task: String,
+    readme: String,
+    tests_dir: PathBuf,
+    dir_path: PathBuf,
+    keep_build: bool,
+}
+
+//result of compiling and testing one student
+struct StudentRun {
+    report: TestReport,
+    elapsed: Duration,
+    kept_build: Option<PathBuf>,
+}
+
+impl StudentJob {
+    // Errors are strings here since they have to cross the thread boundary
+    fn run(&self, key: &str, entry: &SubmissionEntry) -> Result<StudentRun, String> {
+        self.try_run(key, entry).map_err(|e| e.to_string())
+    }
+
+    fn try_run(
+        &self,
+        key: &str,
+        entry: &SubmissionEntry,
+    ) -> Result<StudentRun, Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        let src = entry.src.as_path();
         let mut source_files: Vec<SourceFile> = Vec::new();
-        let (paths, names) = transform_contents(value)?;
-
-        match run_java_tests(value.as_path(), &tests_dir) {
-            Ok(test_results) => {
-                if test_results.starts_with("COMPILATION FAILED:") {
-                    println!("❌ COMPILATION FAILED");
-                } else if test_results.starts_with("NO TEST CLASSES FOUND:") {
-                    println!("⚠️ NO TEST CLASSES FOUND");
-                } else if test_results.starts_with("TEST EXECUTION FAILED:") {
-                    println!("❌ TEST EXECUTION FAILED");
-                } else if test_results.contains("FAILURES!!!")
-                    || test_results.contains("Failures: ") && !test_results.contains("Failures: 0")
-                {
-                    println!("❌ TESTS FAILED");
-                } else if test_results.contains("Tests run:") || test_results.contains("OK (") {
-                    println!("✅ TESTS PASSED");
-                } else {
-                    println!("⚠️ TESTS COMPLETED (unclear status)");
-                }
+        let (paths, names) = transform_contents(src)?;
+
+        let build_dir = tempfile::Builder::new()
+            .prefix(&format!("imagi-{}-", key))
+            .tempdir()?;
+        let (test_results, test_report) =
+            match run_java_tests(&self.config, src, &self.tests_dir, build_dir.path()) {
+                Ok(results) => results,
+                // Still create a payload with the error as test results
+                Err(e) => (format!("ERROR: {}", e), TestReport::error(e.to_string())),
+            };
+        let elapsed = started.elapsed();
+        let kept_build = self.keep_build.then(|| build_dir.keep());
+
+        for (name, path) in names.iter().zip(paths.iter()) {
+            let source_file = parse_source_file(name, path)?;
+            source_files.push(source_file);
+        }
+        let payload = Payload {
+            user_id: key.to_string(),
+            task: self.task.clone(),
+            read_me: self.readme.clone(),
+            source_files,
+            student_tests: student_test_files(src)?,
+            test_results,
+            test_summary: test_report.summary(),
+            test_report: Some(test_report.clone()),
+            commit: entry.commit.clone(),
+            truncation: None,
+        };
+        let json_path_name = format!("{}.json", key);
+        let json_path = self.dir_path.join(json_path_name);
+        std::fs::write(json_path, serde_json::to_string(&payload)?)?;
+        Ok(StudentRun {
+            report: test_report,
+            elapsed,
+            kept_build,
+        })
+    }
+}
 
-                for (name, path) in names.iter().zip(paths.iter()) {
-                    let source_file = parse_source_file(name, path)?;
-                    source_files.push(source_file);
+//table with one row per student and the totals per outcome
+fn print_run_summary(results: &[(String, Result<StudentRun, String>)], total: Duration) {
+    let (mut passed, mut tests_failed, mut compile_failed, mut errors) = (0, 0, 0, 0);
+    let width = results
+        .iter()
+        .map(|(key, _)| key.len())
+        .max()
+        .unwrap_or(0)
+        .max("Student".len());
+
+    println!("\n\x1b[1;34m📋 Test summary\x1b[0m");
+    println!("   {:<width$}  {:>8}  Result", "Student", "Time");
+    for (key, result) in results {
+        match result {
+            Ok(run) => {
+                match run.report.outcome {
+                    TestOutcome::Passed => passed += 1,
+                    TestOutcome::TestsFailed => tests_failed += 1,
+                    TestOutcome::CompilationFailed => compile_failed += 1,
+                    _ => errors += 1,
                 }
-                let payload = create_payload_json(
-                    key.to_string(),
-                    task.clone(),
-                    readme.clone(),
-                    source_files,
-                    test_results,
-                )?;
-                let json_path_name = format!("{}.json", key);
-                let json_path = dir_path.join(json_path_name);
-                std::fs::write(json_path, payload)?;
+                println!(
+                    "   {:<width$}  {:>7.1}s  {}",
+                    key,
+                    run.elapsed.as_secs_f64(),
+                    run.report.status_line()
+                );
             }
             Err(e) => {
-                println!("❌ ERROR: {}", e);
-                // Still try to create a payload with the error as test results
-                for (name, path) in names.iter().zip(paths.iter()) {
-                    let source_file = parse_source_file(name, path)?;
-                    source_files.push(source_file);
-                }
-                let error_test_results = format!("ERROR: {}", e);
-                let payload = create_payload_json(
-                    key.to_string(),
-                    task.clone(),
-                    readme.clone(),
-                    source_files,
-                    error_test_results,
-                )?;
-                let json_path_name = format!("{}.json", key);
-                let json_path = dir_path.join(json_path_name);
-                std::fs::write(json_path, payload)?;
+                errors += 1;
+                println!("   {:<width$}  {:>8}  ❌ ERROR: {}", key, "-", e);
             }
         }
     }
-    Ok(())
+    println!(
+        "\n   ✅ {} passed, ❌ {} with test failures, 🛠️ {} compilation failures, ⚠️ {} errors ({:.1}s total)",
+        passed,
+        tests_failed,
+        compile_failed,
+        errors,
+        total.as_secs_f64()
+    );
 }
 
 //used to get names for test files
-fn find_test_classes(students_repo: PathBuf) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let mut test_names = Vec::new();
-    for file in fs::read_dir(students_repo)? {
-        let file = file?;
-        let path = file.path();
-        if path.is_file() {
-            if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                if (filename.ends_with("Test.java") || filename.ends_with("Tests.java"))
-                    && !filename.ends_with("TextFileTest.java")
-                {
-                    // Remove .java extension to get the class name
-                    let class_name = filename.trim_end_matches(".java").to_string();
-                    test_names.push(class_name);
-                }
-            }
+//returns the fully qualified class name (from the package declaration) and the source path of every test class
+fn find_test_classes(src_dir: &Path) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+    let mut test_classes = Vec::new();
+    for relative in walk_files(src_dir)? {
+        if let Some(filename) = relative.file_name().and_then(|n| n.to_str())
+            && (filename.ends_with("Test.java") || filename.ends_with("Tests.java"))
+            && !filename.ends_with("TextFileTest.java")
+        {
+            // Remove .java extension to get the class name
+            let class_name = filename.trim_end_matches(".java");
+            let path = src_dir.join(&relative);
+            let source = fs::read_to_string(&path)?;
+            let qualified = match java_package(&source) {
+                Some(package) => format!("{}.{}", package, class_name),
+                None => class_name.to_string(),
+            };
+            test_classes.push((qualified, path));
         }
     }
-    Ok(test_names)
+    Ok(test_classes)
+}
+
+//the name from the `package a.b;` declaration, None for the default package
+fn java_package(source: &str) -> Option<String> {
+    source.lines().find_map(|line| {
+        let package = line.trim().strip_prefix("package ")?;
+        Some(package.trim().trim_end_matches(';').trim().to_string())
+    })
 }
 
-//function that runs java commands, copies jars and test files to students repos, and moves their own made tests
+//function that runs java commands in build_dir, a scratch directory populated with the student sources,
+//the instructor tests and the jars. The student's checkout is only read, never modified.
+//Sources go to build_dir/src (keeping their package directories), classes to build_dir/classes.
+//returns the raw compiler/JUnit output together with the parsed report
 pub fn run_java_tests(
+    config: &Config,
     students_src: &Path,
     tests_dir: &Path,
-) -> Result<String, Box<dyn std::error::Error>> {
-    // 1. Move any pre-existing student test files to student_tests/
-    let mut test_files_to_move = Vec::new();
-    let jars_dir = env::var("IMAGI_JARS_DIR").expect("Set the IMAGI_JARS_DIR environment variable");
-
-    for entry in fs::read_dir(students_src)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.contains("Test.java") {
-                    test_files_to_move.push((path.clone(), name.to_string()));
-                }
-            }
+    build_dir: &Path,
+) -> Result<(String, TestReport), Box<dyn std::error::Error>> {
+    let src_dir = build_dir.join("src");
+    let classes_dir = build_dir.join("classes");
+    fs::create_dir_all(&src_dir)?;
+    fs::create_dir_all(&classes_dir)?;
+
+    // 1. Copy the student sources, leaving out their own tests and old class files
+    for relative in walk_files(students_src)? {
+        if let Some(name) = relative.file_name().and_then(|n| n.to_str())
+            && !name.contains("Test.java")
+            && !name.ends_with(".class")
+        {
+            copy_relative(students_src, &relative, &src_dir)?;
         }
     }
 
-    if !test_files_to_move.is_empty() {
-        let student_tests_dir = students_src.join("student_tests");
-        fs::create_dir_all(&student_tests_dir)?;
-        for (path, name) in test_files_to_move {
-            let dest = student_tests_dir.join(name);
-            fs::rename(&path, &dest)?;
+    // 2. Copy test files from tests_dir into build_dir/src
+    for relative in walk_files(tests_dir)? {
+        if let Some(name) = relative.file_name().and_then(|n| n.to_str())
+            && (name.ends_with("Test.java")
+                || name.ends_with("Tests.java")
+                || name.ends_with("test.go")
+                || name.ends_with("Test.go")
+                || name.ends_with("Test.class")
+                || name.ends_with("Tests.class")
+                || name.ends_with("test.class"))
+        {
+            copy_relative(tests_dir, &relative, &src_dir)?;
         }
     }
 
-    // 2. Copy test files from tests_dir into students_src
-    for entry in fs::read_dir(tests_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.ends_with("Test.java")
-                    || name.ends_with("Tests.java")
-                    || name.ends_with("test.go")
-                    || name.ends_with("Test.go")
-                    || name.ends_with("Test.class")
-                    || name.ends_with("Tests.class")
-                    || name.ends_with("test.class")
-                {
-                    let dest = students_src.join(name);
-                    fs::copy(&path, &dest)?;
-                }
-            }
-        }
-    }
-
-    // 3. Copy JAR files from jars_dir into students_src
-    for entry in fs::read_dir(jars_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.ends_with(".jar") {
-                    let dest = students_src.join(name);
-                    fs::copy(&path, &dest)?;
-                }
-            }
-        }
+    // 3. Copy the configured JAR files into build_dir, javac and java run in build_dir/src
+    let mut classpath = String::from("../classes:.");
+    for jar in config.java.jar_paths() {
+        let name = jar
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format!("Invalid jar path: {}", jar.display()))?
+            .to_string();
+        fs::copy(&jar, build_dir.join(&name))
+            .map_err(|e| format!("Could not copy {}: {}", jar.display(), e))?;
+        classpath.push_str(":../");
+        classpath.push_str(&name);
     }
 
     // 4. Compile all the java files - capture output instead of just status
-    let compile_output = Command::new("sh")
-        .arg("-c")
-        .arg("javac -cp '.:junit-4.12.jar:hamcrest-core-1.3.jar' *.java")
-        .current_dir(students_src)
-        .output()?;
+    let java = &config.java;
+    let sources: Vec<PathBuf> = walk_files(&src_dir)?
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "java"))
+        .collect();
+    let mut javac = Command::new("javac");
+    javac
+        .arg(format!("-J-Xmx{}", java.max_heap))
+        .arg("-cp")
+        .arg(&classpath)
+        .arg("-sourcepath")
+        .arg(".")
+        .arg("-d")
+        .arg("../classes")
+        .args(&sources)
+        .current_dir(&src_dir);
+    let compile_output = run_limited(
+        &mut javac,
+        Duration::from_secs(java.compile_timeout_secs),
+        java.max_memory_mb,
+    )?;
+    let compile_stdout = &compile_output.stdout;
+    let compile_stderr = &compile_output.stderr;
+
+    if compile_output.timed_out {
+        return Ok((
+            format!("TIMED OUT:\n{}\n{}", compile_stdout, compile_stderr),
+            TestReport::timed_out("compilation", java.compile_timeout_secs),
+        ));
+    }
 
-    if !compile_output.status.success() {
+    if !compile_output.status.is_some_and(|s| s.success()) {
         // Return compilation error as test results instead of failing
-        let compile_stdout = String::from_utf8_lossy(&compile_output.stdout);
-        let compile_stderr = String::from_utf8_lossy(&compile_output.stderr);
-        return Ok(format!(
+        let output = format!("{}\n{}", compile_stderr, compile_stdout);
+        if is_out_of_memory(&output) {
+            return Ok((
+                format!("OUT OF MEMORY:\n{}", output),
+                TestReport::compilation_failed(&output).out_of_memory(),
+            ));
+        }
+        let raw = format!(
             "COMPILATION FAILED:\n{}\n{}",
             compile_stdout, compile_stderr
-        ));
+        );
+        return Ok((raw, TestReport::compilation_failed(&output)));
     }
 
     // 5. Find test classes and run the tests (only if compilation succeeded)
-    let test_classes = find_test_classes(students_src.to_path_buf())?;
+    let test_classes = find_test_classes(&src_dir)?;
 
     // Check if no test classes were found
     if test_classes.is_empty() {
-        return Ok("NO TEST CLASSES FOUND: No *Test.java or *Tests.java files were found after compilation.".to_string());
+        return Ok((
+            "NO TEST CLASSES FOUND: No *Test.java or *Tests.java files were found after compilation.".to_string(),
+            TestReport::no_test_classes(),
+        ));
     }
 
-    let run = Command::new("java")
+    // JUnitCore only prints the failing tests, the passing ones are taken from the sources
+    let mut known_tests = Vec::new();
+    for (class, path) in &test_classes {
+        let source = fs::read_to_string(path)?;
+        for method in find_test_methods(&source) {
+            known_tests.push((class.clone(), method));
+        }
+    }
+
+    let mut junit = Command::new("java");
+    junit
+        .arg(format!("-Xmx{}", java.max_heap))
         .arg("-cp")
-        .arg(".:junit-4.12.jar:hamcrest-core-1.3.jar")
+        .arg(&classpath)
         .arg("org.junit.runner.JUnitCore")
-        .args(&test_classes)
-        .current_dir(students_src)
-        .output()?;
+        .args(test_classes.iter().map(|(class, _)| class))
+        .current_dir(&src_dir);
+    let run = run_limited(
+        &mut junit,
+        Duration::from_secs(java.test_timeout_secs),
+        java.max_memory_mb,
+    )?;
+
+    // 6. Return test results (stdout + stderr)
+    let stdout = &run.stdout;
+    let stderr = &run.stderr;
+    if run.timed_out {
+        return Ok((
+            format!("TIMED OUT:\n{}\n{}", stdout, stderr),
+            TestReport::timed_out("test run", java.test_timeout_secs),
+        ));
+    }
 
-    // Check if test execution failed (not just test failures, but execution failure)
-    if !run.status.success() {
-        let stdout = String::from_utf8_lossy(&run.stdout);
-        let stderr = String::from_utf8_lossy(&run.stderr);
-        return Ok(format!("TEST EXECUTION FAILED:\n{}\n{}", stdout, stderr));
+    let mut report = parse_junit_output(stdout, &known_tests);
+    if is_out_of_memory(stdout) || is_out_of_memory(stderr) {
+        return Ok((
+            format!("OUT OF MEMORY:\n{}\n{}", stdout, stderr),
+            report.out_of_memory(),
+        ));
     }
 
-    // 6. Return test results (stdout + stderr)
-    let stdout = String::from_utf8_lossy(&run.stdout);
-    let stderr = String::from_utf8_lossy(&run.stderr);
+    // JUnitCore also exits non-zero when tests fail, so only a run without any JUnit summary
+    // counts as an execution failure (class not found, JVM crash, ...)
+    if report.outcome == TestOutcome::ExecutionFailed {
+        if !stderr.trim().is_empty() {
+            report.message = Some(stderr.trim().to_string());
+        }
+        return Ok((
+            format!("TEST EXECUTION FAILED:\n{}\n{}", stdout, stderr),
+            report,
+        ));
+    }
 
-    Ok(format!("{}\n{}", stdout, stderr))
+    Ok((format!("{}\n{}", stdout, stderr), report))
 }
 
 // Print only the test_results field from JSON file(s) with clear terminal output.
@@ -370,12 +757,21 @@ pub fn print_test_results(json_path: PathBuf) -> Result<(), Box<dyn std::error::
     fn print_test_result_from_file(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         let data = fs::read_to_string(path)?;
         let v: Value = serde_json::from_str(&data)?;
-        let test_results = v
-            .get("test_results")
-            .and_then(|tr| tr.as_str())
-            .unwrap_or("<no test_results field>");
         println!("\x1b[1;34mFile: {}\x1b[0m", path.display());
-        println!("\x1b[1;32mTest Results:\x1b[0m\n{}", test_results.trim());
+        // Payloads written before test_report existed only have the raw text
+        match v.get("test_report").cloned() {
+            Some(report) if !report.is_null() => {
+                let report: TestReport = serde_json::from_value(report)?;
+                print_test_report(&report);
+            }
+            _ => {
+                let test_results = v
+                    .get("test_results")
+                    .and_then(|tr| tr.as_str())
+                    .unwrap_or("<no test_results field>");
+                println!("\x1b[1;32mTest Results:\x1b[0m\n{}", test_results.trim());
+            }
+        }
         Ok(())
     }
 
@@ -404,6 +800,46 @@ pub fn print_test_results(json_path: PathBuf) -> Result<(), Box<dyn std::error::
     Ok(())
 }
 
+fn print_test_report(report: &TestReport) {
+    println!("\x1b[1;32mTest Results:\x1b[0m {}", report.status_line());
+    if report.tests_run > 0 {
+        print!(
+            "   {} run, {} passed, {} failed",
+            report.tests_run,
+            report.passed(),
+            report.failures
+        );
+        match report.time_secs {
+            Some(time) => println!(" in {:.3}s", time),
+            None => println!(),
+        }
+    }
+    if let Some(message) = &report.message {
+        println!("   {}", message.trim());
+    }
+    for diagnostic in &report.diagnostics {
+        println!(
+            "   \x1b[1;31m{}:{}\x1b[0m {}: {}",
+            diagnostic.file,
+            diagnostic.line.map(|l| l.to_string()).unwrap_or_default(),
+            diagnostic.kind,
+            diagnostic.message
+        );
+        for line in &diagnostic.context {
+            println!("      \x1b[90m{}\x1b[0m", line);
+        }
+    }
+    for test in report.failed_tests() {
+        println!("   \x1b[1;31m✗ {}.{}\x1b[0m", test.class, test.name);
+        if let Some(message) = &test.message {
+            println!("      {}", message);
+        }
+        for frame in &test.stack_trace {
+            println!("      \x1b[90mat {}\x1b[0m", frame);
+        }
+    }
+}
+
 // Print only the feedback fields from JSON file(s) with clear terminal output.
 pub fn print_feedback(json_path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     use serde_json::Value;
@@ -417,18 +853,37 @@ pub fn print_feedback(json_path: PathBuf) -> Result<(), Box<dyn std::error::Erro
             .get("student_id")
             .and_then(|id| id.as_str())
             .unwrap_or("<no student_id>");
-        let status = v
+        // An unknown status means the file was edited by hand or is not a feedback file
+        let status: GradeStatus = v
             .get("status")
             .and_then(|s| s.as_str())
-            .unwrap_or("<no status>");
+            .ok_or_else(|| format!("{}: no status", path.display()))?
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
         let feedback = v
             .get("feedback")
             .and_then(|f| f.as_str())
             .unwrap_or("<no feedback>");
         println!("\x1b[1;34mFile: {}\x1b[0m", path.display());
         println!("\x1b[1;33mStudent ID:\x1b[0m {}", student_id);
-        println!("\x1b[1;32mStatus:\x1b[0m {}", status);
+        println!("\x1b[1;32mStatus:\x1b[0m {}", status.colored(0));
+        if let Some(confidence) = v.get("confidence").and_then(|c| c.as_f64()) {
+            println!("\x1b[1;32mConfidence:\x1b[0m {:.0}%", confidence * 100.0);
+        }
         println!("\x1b[1;36mFeedback:\x1b[0m\n{}", feedback.trim());
+        // Feedback files from older versions have no issue list
+        let issues = v.get("issues").and_then(|i| i.as_array());
+        if let Some(issues) = issues.filter(|i| !i.is_empty()) {
+            println!("\x1b[1;31mIssues:\x1b[0m");
+            for issue in issues {
+                let file = issue["file"].as_str().unwrap_or("?");
+                let message = issue["message"].as_str().unwrap_or("");
+                match issue["line"].as_u64() {
+                    Some(line) => println!("   {}:{} {}", file, line, message),
+                    None => println!("   {} {}", file, message),
+                }
+            }
+        }
         Ok(())
     }
 
@@ -460,241 +915,299 @@ pub fn print_feedback(json_path: PathBuf) -> Result<(), Box<dyn std::error::Erro
 //sending payload to the AI-api. Receiving back the graded feedback as well
 //model parameter determines which AI model to use ("openai" or "gemini")
 pub async fn send_payload(
+    config: &Config,
     json_dir: PathBuf,
     output_dir: PathBuf,
     model: Option<&str>,
+    post_policy: Option<PostPolicy>,
+    force: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // 1. Create output directory
     fs::create_dir_all(&output_dir)?;
 
-    // Define API endpoint and server command based on model choice
-    let model_str = model.unwrap_or("openai");
-    let api_endpoint = match model_str {
-        "gemini" => "http://127.0.0.1:8000/imagi_gemini",
-        _ => "http://127.0.0.1:8000/imagi_gpt", // Default to OpenAI
-    };
+    // An unattended run should fail now, not after grading everyone
+    if post_policy.is_some_and(|p| p != PostPolicy::Never)
+        && !config.dry_run.enabled
+        && env::var("GITHUB_TOKEN").is_err()
+    {
+        return Err("Set the GITHUB_TOKEN environment variable".into());
+    }
 
-    // Get project root from environment variable
-    let project_root = PathBuf::from(
-        env::var("IMAGI_ROOT")
-            .map_err(|_| "IMAGI_ROOT environment variable not set. Please set it to the path containing the AI_api directory.")?
+    // The provider comes from -m, falling back to llm.provider in the config
+    let provider = provider_from_config(config, model)?;
+    let budget = prompt_budget(config, model)?;
+    let mut session = UsageSession::new(
+        config,
+        model.unwrap_or(&config.llm.provider),
+        provider.model(),
     );
-
-    // Verify AI_api directory exists
-    if !project_root.join("AI_api").exists() {
-        return Err(format!(
-            "AI_api directory not found in: {}. Please check your IMAGI_ROOT setting.",
-            project_root.display()
-        )
-        .into());
+    println!("🤖 Grading with {}", provider.name());
+    if let Some(policy) = post_policy {
+        println!("📬 Non-interactive, post policy: {}", policy);
     }
-
-    // Start the API server with the appropriate module and venv if needed
-    let mut server = if model_str == "gemini" {
-        // Check if venv exists (required for Gemini API, especially on Arch Linux)
-        let venv_python = project_root.join("AI_api/venv/bin/python");
-        if !venv_python.exists() {
-            return Err("Virtual environment not found for Gemini API. Please create it using:\n\npython -m venv venv\nsource venv/bin/activate\npip install google-genai fastapi uvicorn\n\nAlternatively, edit github_api.rs to use system Python if your distro supports it.".into());
-        }
-
-        // Use Python from venv to run the server with geminiAPI module
-        Command::new(venv_python)
-            .arg("-m")
-            .arg("uvicorn")
-            .arg("AI_api.geminiAPI:app")
-            .current_dir(&project_root)
-            .spawn()?
-
-        // UNCOMMENT THIS SECTION AND COMMENT OUT THE ABOVE SECTION IF YOU DON'T NEED A VIRTUAL ENVIRONMENT
-        //
-        // On Arch Linux, pip packages cannot be installed system-wide, so we use venv.
-        // On Ubuntu/Debian and other systems where pip allows global installs, you can use this instead.
-        // Remember to install the required packages with: pip install google-genai fastapi uvicorn
-        //
-        // Command::new("python")
-        //     .arg("-m")
-        //     .arg("uvicorn")
-        //     .arg("AI_api.geminiAPI:app")
-        //     .current_dir(&project_root)
-        //     .spawn()?
-    } else {
-        // Use Python from venv to run the server with gptAPI module
-        let venv_python = project_root.join("AI_api/venv/bin/python");
-        if !venv_python.exists() {
-            return Err("Virtual environment not found for OpenAI API. Please create it using:\n\npython -m venv AI_api/venv\nsource AI_api/venv/bin/activate\npip install fastapi uvicorn openai pydantic\n\nAlternatively, edit github_api.rs to use system Python if your distro supports it.".into());
-        }
-
-        Command::new(venv_python)
-            .arg("-m")
-            .arg("uvicorn")
-            .arg("AI_api.gptAPI:app")
-            .current_dir(&project_root)
-            .spawn()?
-    };
-
-    // 2. Wait a moment to let the server boot
-    wait_for_server_ready().await;
-
-    let api = reqwest::Client::new();
-    for file in fs::read_dir(json_dir)? {
-        let file = file?;
-        let path = file.path();
+    let mut policy = post_policy;
+    let mut failures = Vec::new();
+    let mut posted = 0;
+    let mut queued = 0;
+    let mut cached = 0;
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(json_dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
         if path.is_file() {
-            let content = fs::read_to_string(path)?; //basic post request
-            let post = api
-                .post(api_endpoint) // Use model-specific endpoint (either /imagi_gpt or /imagi_gemini)
-                .header("Content-Type", "application/json")
-                .body(content)
-                .send()
-                .await?;
-
-            if post.status().is_success() {
-                let feedback: serde_json::Value = post.json().await?;
-                let student_id = feedback["student_id"].as_str().unwrap_or("");
-                let task = feedback["task"].as_str().unwrap_or("");
-                let status = feedback["status"].as_str().unwrap_or("");
-                let ai_feedback = feedback["feedback"].as_str().unwrap_or("");
-                let feedback_json = create_feedback_json(
-                    student_id.to_string(),
-                    status.to_string(),
-                    ai_feedback.to_string(),
-                )?;
-                let json_path_name = format!("{}_feedback.json", student_id);
-                let json_path = output_dir.join(json_path_name);
-                fs::write(&json_path, feedback_json)?;
-
-                let mut response = String::new();
-
-                // Print formatted output with colors and better layout
-                println!("\n{}", "=".repeat(80));
-                println!(
-                    "\x1b[1;36m📝 FEEDBACK READY FOR: {}\x1b[0m",
-                    student_id.to_uppercase()
-                );
-                println!("{}", "=".repeat(80));
-
-                println!("\x1b[1;33m📋 Task:\x1b[0m {}", task);
-                println!("\x1b[1;32m✅ Status:\x1b[0m {}", status);
-
-                println!("\n\x1b[1;35m💬 Generated Feedback:\x1b[0m");
-                println!("{}", "-".repeat(50));
-                // Format feedback with proper line breaks and indentation
-                for line in ai_feedback.lines() {
-                    println!("  {}", line);
-                }
-                println!("{}", "-".repeat(50));
-
-                println!(
-                    "\n\x1b[1;34m🤔 Would you like to create a GitHub issue for this student?\x1b[0m"
-                );
-                println!(
-                    "   \x1b[32m[y]\x1b[0m Yes, create issue   \x1b[31m[n]\x1b[0m No, save locally only"
-                );
-                print!("\x1b[1;37m➤ Your choice: \x1b[0m");
-                use std::io::{self, Write};
-                io::stdout().flush()?;
-
-                io::stdin().read_line(&mut response)?;
-                while response.trim() != "n" && response.trim() != "y" {
+            let student = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            // A failing student is recorded and the others are still graded
+            let graded = grade_student(
+                config,
+                provider.as_ref(),
+                &path,
+                &output_dir,
+                budget,
+                force,
+                &mut session,
+            )
+            .await;
+            session.save(&output_dir)?;
+            let (payload, grade, mut item, item_path) = match graded {
+                Ok(Some(graded)) => graded,
+                Ok(None) => {
                     println!(
-                        "\x1b[1;31m❌ Invalid input!\x1b[0m Please enter \x1b[32m'y'\x1b[0m or \x1b[31m'n'\x1b[0m:"
+                        "⏭️  {} was already graded with the same payload, prompt and model (use --force to grade again)",
+                        student
                     );
-                    print!("\x1b[1;37m➤ Your choice: \x1b[0m");
-                    io::stdout().flush()?;
-                    response.clear();
-                    io::stdin().read_line(&mut response)?;
+                    cached += 1;
+                    continue;
                 }
-                if response.trim() == "y" {
-                    // Ask for teacher feedback
+                Err(e) => {
+                    // Never post anything for a student the grader could not handle
+                    eprintln!("\x1b[1;31m❌ Could not grade {}: {}\x1b[0m", student, e);
+                    failures.push(FailedStudent {
+                        student_id: student,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            print_review_item(&item);
+
+            // --non-interactive (or after quitting the review): the policy decides,
+            // everything it does not allow stays pending for a TA
+            if let Some(policy) = policy {
+                match policy.check(&grade, payload.test_report.as_ref()) {
+                    Ok(()) => {
+                        item.post(config, &output_dir).await?;
+                    }
+                    Err(_) if post_policy.is_none() => {
+                        item.reason = Some("not reviewed yet".to_string());
+                        item.save(&output_dir)?;
+                    }
+                    Err(reason) => {
+                        item.reason = Some(reason);
+                        item.save(&output_dir)?;
+                    }
+                }
+                if item.state == ReviewState::Posted {
+                    posted += 1;
+                } else {
+                    queued += 1;
                     println!(
-                        "\n\x1b[1;34m🧑‍🏫 Would you like to add your own feedback before creating the issue?\x1b[0m"
+                        "\n\x1b[1;33m🗂️  Queued for review:\x1b[0m {}",
+                        item.reason.as_deref().unwrap_or_default()
                     );
+                    println!("   \x1b[90m💾 Location: {}\x1b[0m", item_path.display());
+                }
+                println!("{}\n", "=".repeat(80));
+                continue;
+            }
+
+            match review_item(config, &mut item, &output_dir).await? {
+                Decision::Posted => posted += 1,
+                Decision::Pending => queued += 1,
+                Decision::Rejected => {}
+                Decision::Quit => {
+                    // Keep grading, but nobody is answering prompts any more
                     println!(
-                        "   \x1b[32m[y]\x1b[0m Yes, add my feedback   \x1b[31m[n]\x1b[0m No, use AI feedback only"
+                        "   \x1b[90m⏹️  Stopped reviewing, the remaining students are graded and queued\x1b[0m"
                     );
-                    print!("\x1b[1;37m➤ Your choice: \x1b[0m");
-                    io::stdout().flush()?;
+                    queued += 1;
+                    policy = Some(PostPolicy::Never);
+                }
+            }
+            println!("{}\n", "=".repeat(80));
+        }
+    }
+    if config.dry_run.enabled {
+        println!(
+            "🧪 Dry run, nothing was posted: the issues are in {} and stay in the review queue",
+            config.dry_run.outbox.display()
+        );
+    } else {
+        println!("📬 Posted {} issue(s)", posted);
+    }
+    if queued > 0 {
+        println!(
+            "🗂️  {} student(s) waiting for review in {}, run `imagi review -o {}`",
+            queued,
+            review_dir(&output_dir).display(),
+            output_dir.display()
+        );
+    }
+    if cached > 0 {
+        println!("⏭️  {} student(s) skipped, already graded", cached);
+    }
+    session.print_summary(config, &output_dir);
+    if let Err(e) = session.append_to_ledger(config, &output_dir) {
+        eprintln!("\x1b[33m⚠️  {}\x1b[0m", e);
+    }
 
-                    let mut teacher_response = String::new();
-                    io::stdin().read_line(&mut teacher_response)?;
+    // The failures of the last run, gone once everyone could be graded
+    let failed_path = output_dir.join("failed.json");
+    if failures.is_empty() {
+        if failed_path.exists() {
+            fs::remove_file(&failed_path)?;
+        }
+        return Ok(());
+    }
+    fs::write(&failed_path, serde_json::to_string_pretty(&failures)?)?;
+    let names: Vec<&str> = failures.iter().map(|f| f.student_id.as_str()).collect();
+    Err(format!(
+        "{} student(s) could not be graded and got no issue: {} (details in {}, run generate again to retry them)",
+        failures.len(),
+        names.join(", "),
+        failed_path.display()
+    )
+    .into())
+}
 
-                    let complete_feedback = if teacher_response.trim() == "y" {
-                        // Get teacher's feedback
-                        println!(
-                            "\n\x1b[1;36m📝 Enter your feedback (type 'DONE' on a new line when finished):\x1b[0m"
-                        );
-                        let mut teacher_feedback = String::new();
-                        let mut line = String::new();
-
-                        loop {
-                            line.clear();
-                            io::stdin().read_line(&mut line)?;
-                            if line.trim() == "DONE" {
-                                break;
-                            }
-                            teacher_feedback.push_str(&line);
-                        }
+//a graded student, saved as a pending review item at the path
+type GradedStudent = (Payload, GradeResponse, ReviewItem, PathBuf);
+
+//Grades one payload file and saves the feedback file and the review item. Everything that can
+//go wrong for a single student happens here, so the caller can record it and go on.
+//None if an earlier run graded the same payload with the same prompt and model and its review
+//item is still there.
+async fn grade_student(
+    config: &Config,
+    provider: &dyn LlmProvider,
+    path: &Path,
+    output_dir: &Path,
+    budget: usize,
+    force: bool,
+    session: &mut UsageSession,
+) -> Result<Option<GradedStudent>, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)?;
+    let payload: Payload = serde_json::from_str(&content)
+        .map_err(|e| format!("{} is not a payload: {}", path.display(), e))?;
+    // The model only ever sees the pseudonymized payload, shortened for this model's budget.
+    // The rest of the run works on the full, real one.
+    let mut prompted = payload.clone();
+    let mut pseudonyms = Pseudonyms::new(config, &payload.user_id)?;
+    if let Some(pseudonyms) = pseudonyms.as_mut() {
+        pseudonyms.scrub_payload(&mut prompted);
+    }
+    let prompt = build_prompt(config, &mut prompted, budget)?;
+    // the filenames the model sees, its issues have to point at one of them
+    let files = referable_files(&prompted);
+    if let Some(pseudonyms) = &pseudonyms {
+        pseudonyms.restore_payload(&mut prompted);
+    }
+    let truncation = prompted.truncation;
+    let restore = |text: &str| match &pseudonyms {
+        Some(pseudonyms) => pseudonyms.restore(text),
+        None => text.to_string(),
+    };
+    let key = cache_key(&content, &prompt, &provider.name());
 
-                        // Combine teacher's feedback with AI feedback
-                        format!(
-                            "👨‍🏫 **Teacher's note**:\n\n{}\n\n---\n\n🤖 **AI Suggestions** (optional improvements, not requirements):\n\n{}",
-                            teacher_feedback.trim(),
-                            ai_feedback
-                        )
-                    } else {
-                        // Use only AI feedback but include AI suggestions header with disclaimer
-                        format!(
-                            "🤖 **AI Suggestions** (optional improvements, not requirements):\n\n{}\n\nNote: These suggestions are meant to help you learn and improve - they are not mandatory requirements that must be completed.",
-                            ai_feedback
-                        )
-                    };
-
-                    send_issue(
-                        task.to_string(),
-                        student_id.to_string(),
-                        status.to_string(),
-                        complete_feedback,
-                    )
-                    .await?;
-                } else if response.trim() == "n" {
-                    println!(
-                        "\n\x1b[1;33m📁 Feedback saved locally for {}\x1b[0m",
-                        student_id
-                    );
-                    println!(
-                        "   \x1b[90m💾 Location: {}\x1b[0m",
-                        &json_path.to_string_lossy()
-                    );
-                    println!("   \x1b[90m🚫 No GitHub issue will be created.\x1b[0m");
+    let cached = if force {
+        None
+    } else {
+        load_cached(output_dir, &payload.user_id, &key)
+    };
+    let grade = match cached {
+        Some(_) if review_item_path(output_dir, &payload.user_id).exists() => {
+            return Ok(None);
+        }
+        // the answer is reused, only the files below are written again
+        Some(grade) => grade,
+        None => {
+            if let Some(truncation) = &truncation {
+                for line in truncation_lines(&payload.user_id, truncation) {
+                    println!("\x1b[33m{}\x1b[0m", line);
+                }
+            }
+            let files: Vec<&str> = files.iter().map(String::as_str).collect();
+            match grade(provider, &prompt, &files, config.llm.max_attempts).await {
+                Ok((mut grade, usage)) => {
+                    session.record(&payload.user_id, &payload.task, usage, true);
+                    if let Some(pseudonyms) = &pseudonyms {
+                        pseudonyms.restore_grade(&mut grade);
+                    }
+                    grade
+                }
+                Err(e) => {
+                    session.record(&payload.user_id, &payload.task, e.usage.clone(), false);
+                    if !e.answers.is_empty() {
+                        let rejected = output_dir.join(format!("{}_rejected.txt", payload.user_id));
+                        fs::write(&rejected, restore(&e.answers.join("\n\n---\n\n")))?;
+                        eprintln!(
+                            "   \x1b[90mRejected answers saved to {}\x1b[0m",
+                            rejected.display()
+                        );
+                    }
+                    return Err(restore(&e.to_string()).into());
                 }
-
-                println!("{}\n", "=".repeat(80));
-            } else {
-                let err_text = post.text().await?;
-                eprintln!("Error: {}", err_text);
             }
         }
-    }
-    server.kill()?; // After grading is done
-    Ok(())
+    };
+    store_cached(output_dir, &payload.user_id, &key, &provider.name(), &grade)?;
+
+    let feedback_json = create_feedback_json(payload.user_id.clone(), &grade)?;
+    let json_path = output_dir.join(format!("{}_feedback.json", payload.user_id));
+    fs::write(&json_path, feedback_json)?;
+
+    // Saved as pending before anyone looks at it, `imagi review` picks up whatever is left
+    let mut item = ReviewItem::new(&payload, &grade, truncation);
+    let item_path = item.save(output_dir)?;
+    Ok(Some((payload, grade, item, item_path)))
+}
+
+//what became of an issue
+pub enum IssueOutcome {
+    Created,
+    Rejected,
+    // --dry-run, the request is in this outbox file
+    DryRun(PathBuf),
 }
 
 //function to create github issue with the AI feedback
-async fn send_issue(
+pub async fn send_issue(
+    config: &Config,
     task: String,
     student: String,
-    status: String,
+    status: GradeStatus,
     feedback: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let token = env::var("GITHUB_TOKEN").expect("Set the GITHUB_TOKEN environment variable");
-    let org = "inda-25";
-    let repo = format!("{}-{}", student, task);
-    let url = format!(
-        "https://gits-15.sys.kth.se/api/v3/repos/{}/{}/issues",
-        org, repo
-    );
+) -> Result<IssueOutcome, Box<dyn std::error::Error>> {
+    let url = config.forge.issues_url(&student, &task);
+    let issue = create_issue(status, feedback)?;
+    if config.dry_run.enabled {
+        let path = record_request(
+            &config.dry_run.outbox,
+            "POST",
+            &url,
+            serde_json::to_value(&issue)?,
+            &student,
+            &task,
+        )?;
+        return Ok(IssueOutcome::DryRun(path));
+    }
 
-    let issue = create_issue(status, feedback);
+    let token =
+        env::var("GITHUB_TOKEN").map_err(|_| "Set the GITHUB_TOKEN environment variable")?;
     let mut headers = HeaderMap::new();
 
     headers.insert(USER_AGENT, HeaderValue::from_static("AI-Grader"));
@@ -703,103 +1216,61 @@ async fn send_issue(
         HeaderValue::from_str(&format!("token {}", token))?,
     );
 
-    let client = reqwest::Client::new();
+    let client = http_client(&config.http);
     let res = client
-        .post(&url)
-        .headers(headers)
-        .json(&issue)
-        .send()
-        .await?;
+        .send_once(client.post(&url).headers(headers).json(&issue), "GitHub")
+        .await
+        .map_err(|e| format!("Could not reach GitHub at {}: {}", url, e))?;
 
     if res.status().is_success() {
         println!("\n\x1b[1;32m✅ SUCCESS: GitHub issue created!\x1b[0m");
         println!("   \x1b[90m🔗 Issue posted to repository successfully\x1b[0m");
+        Ok(IssueOutcome::Created)
     } else {
         let error_msg = res.text().await?;
         println!("\n\x1b[1;31m❌ FAILED: Could not create GitHub issue\x1b[0m");
         println!("   \x1b[90m🔍 Error details: {}\x1b[0m", error_msg);
+        Ok(IssueOutcome::Rejected)
     }
-
-    Ok(())
-}
-
-//function to wait for server response. called when when start up the server
-async fn wait_for_server_ready() {
-    let client = Client::new();
-    for _ in 0..30 {
-        // Try for up to 30 seconds
-        match client.get("http://127.0.0.1:8000/docs").send().await {
-            Ok(resp) if resp.status().is_success() => {
-                println!("Server is ready!");
-                return;
-            }
-            _ => {
-                println!("Waiting for server...");
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            }
-        }
-    }
-    panic!("Server did not start in time!");
 }
 
 pub async fn check_issues(
+    config: &Config,
     students: PathBuf,
     task: String,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let token = env::var("GITHUB_TOKEN").expect("Set the GITHUB_TOKEN environment variable");
-    let file = File::open(students)?;
-    let buf = std::io::BufReader::new(file);
-    let mut students_list = Vec::new();
+    let token =
+        env::var("GITHUB_TOKEN").map_err(|_| "Set the GITHUB_TOKEN environment variable")?;
     let mut list_issues: Vec<StatusIssue> = Vec::new();
-    for line in buf.lines() {
-        let student = line?.trim().to_string();
-        if student.is_empty() || student.starts_with('#') {
-            continue; // Skip empty lines and comments
-        }
-        students_list.push(student);
-    }
-    let org = "inda-25";
-    for student in students_list {
-        let repo = format!("{}-{}", student, task);
-        let url = format!(
-            "https://gits-15.sys.kth.se/api/v3/repos/{}/{}/issues",
-            org, repo
-        );
-        let client = reqwest::Client::new();
-        let titles = ["PASS", "FAIL", "KOMP", "KOMPLETTERING"];
+    for entry in read_roster(&students)? {
+        let student = entry.student;
+        let url = config.forge.issues_url(&student, &task);
+        let client = http_client(&config.http);
         let mut headers = HeaderMap::new();
         headers.insert(USER_AGENT, HeaderValue::from_static("AI-Grader"));
         headers.insert(
             AUTHORIZATION,
             HeaderValue::from_str(&format!("token {}", token))?,
         );
-        let response = client.get(&url).headers(headers).send().await?;
+        let response = client
+            .send(client.get(&url).headers(headers), "GitHub")
+            .await
+            .map_err(|e| format!("Could not reach GitHub at {}: {}", url, e))?;
 
         if response.status().is_success() {
             let issues: Vec<IssueTitle> = response.json().await?;
 
-            // Check if any issue has the title we're looking for
+            // Every issue with a status in its title gets a row, students without one are NOT_GRADED
             let mut found_matching_title = false;
             for issue in issues {
-                for title in titles {
-                    // Convert both to uppercase for case-insensitive matching
-                    let issue_upper = issue.title.to_uppercase();
-                    let title_upper = title.to_uppercase();
-
-                    // Check if the issue title contains our status keyword
-                    if issue_upper.contains(&title_upper) {
-                        let status = title.to_string(); // Use our known status, not the full title
-                        let student_issue = parse_issue_status(&student, &status)?;
-                        list_issues.push(student_issue);
-                        found_matching_title = true;
-                        break;
-                    }
+                if let Some(status) = GradeStatus::from_issue_title(&issue.title) {
+                    list_issues.push(parse_issue_status(&student, status)?);
+                    found_matching_title = true;
                 }
             }
 
-            // If no matching title found, add NULL status
             if !found_matching_title {
-                let student_issue = parse_issue_status(&student, "NULL")?;
+                let student_issue = parse_issue_status(&student, GradeStatus::NotGraded)?;
                 list_issues.push(student_issue);
             }
         } else {
@@ -810,20 +1281,11 @@ pub async fn check_issues(
 
     // Print the formatted data table
     println!("\n{}", "=".repeat(60));
-    println!("| {:<20} | {:<15} | {:<5} |", "STUDENT", "STATUS", "");
-    println!("|{:-<22}|{:-<17}|{:-<7}|", "", "", "");
+    println!("| {:<20} | {:<18} |", "STUDENT", "STATUS");
+    println!("|{:-<22}|{:-<20}|", "", "");
 
     for issue in &list_issues {
-        let emoji = match issue.status.as_str() {
-            "PASS" => "✅",
-            "FAIL" => "❌",
-            "KOMP" | "KOMPLETTERING" => "🔄",
-            _ => "❓",
-        };
-        println!(
-            "| {:<20} | {:<15} | {:<5} |",
-            issue.studentid, issue.status, emoji
-        );
+        println!("| {:<20} | {} |", issue.studentid, issue.status.colored(15));
     }
     println!("{}", "=".repeat(60));
 
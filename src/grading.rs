@@ -0,0 +1,414 @@
+use crate::budget::test_output;
+use crate::json_parser::Payload;
+use crate::llm::LlmProvider;
+use crate::usage::Usage;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+// Appended to every prompt template, this is the only answer format parse_grade accepts.
+pub const OUTPUT_CONTRACT: &str = r#"
+
+Output Format:
+
+Answer with ONLY a JSON object, without markdown code fences and without any text before or after it:
+{
+  "status": "PASS" | "FAIL" | "KOMPLETTERING",
+  "feedback": "<the feedback described above, as markdown>",
+  "issues": [{"file": "<one of the submitted filenames, or a test file from the test results>", "line": <line number or null>, "message": "<short description>"}],
+  "confidence": <number between 0 and 1, how sure you are about the status>
+}
+- "status" is "PASS" when all unit tests passed and the task is solved, "KOMPLETTERING" when the student has to fix something, "FAIL" when the submission is missing or does not attempt the task.
+- "issues" lists every problem the feedback mentions, use an empty list when there are none.
+"#;
+
+//The status of a submission, shared by the grader's answer, the feedback files and the issue titles.
+//NotGraded is never an answer from the grader, it is what check_issues reports for a student without a status issue.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum GradeStatus {
+    Pass,
+    Fail,
+    Komplettering,
+    NotGraded,
+}
+
+// every status the grader may answer with, in the order they are listed in error messages
+const GRADES: [GradeStatus; 3] = [
+    GradeStatus::Pass,
+    GradeStatus::Fail,
+    GradeStatus::Komplettering,
+];
+
+impl GradeStatus {
+    //the canonical spelling, used in feedback files and as the issue title
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GradeStatus::Pass => "PASS",
+            GradeStatus::Fail => "FAIL",
+            GradeStatus::Komplettering => "KOMPLETTERING",
+            GradeStatus::NotGraded => "NOT_GRADED",
+        }
+    }
+
+    pub fn is_graded(&self) -> bool {
+        *self != GradeStatus::NotGraded
+    }
+
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            GradeStatus::Pass => "✅",
+            GradeStatus::Fail => "❌",
+            GradeStatus::Komplettering => "🔄",
+            GradeStatus::NotGraded => "❓",
+        }
+    }
+
+    // ANSI colour code for the terminal
+    pub fn color(&self) -> &'static str {
+        match self {
+            GradeStatus::Pass => "1;32",
+            GradeStatus::Fail => "1;31",
+            GradeStatus::Komplettering => "1;33",
+            GradeStatus::NotGraded => "90",
+        }
+    }
+
+    //e.g. "✅ PASS" in green, width pads the name so tables stay aligned
+    pub fn colored(&self, width: usize) -> String {
+        format!(
+            "\x1b[{}m{} {:<width$}\x1b[0m",
+            self.color(),
+            self.emoji(),
+            self.as_str(),
+            width = width
+        )
+    }
+
+    //Finds the status in an issue title. create_issue writes the bare status, a TA can add to it
+    //as "[PASS] ..." or "PASS: ...". Only the canonical uppercase spelling at the start counts,
+    //students open issues too and "how do I pass test 3" is not a PASS.
+    pub fn from_issue_title(title: &str) -> Option<GradeStatus> {
+        let title = title.trim();
+        GRADES.into_iter().find(|status| {
+            let name = status.as_str();
+            title == name
+                || title
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.starts_with(':'))
+                || title
+                    .strip_prefix('[')
+                    .and_then(|rest| rest.strip_prefix(name))
+                    .is_some_and(|rest| rest.starts_with(']'))
+        })
+    }
+}
+
+impl FromStr for GradeStatus {
+    type Err = String;
+
+    //case-insensitive, KOMP is short for KOMPLETTERING and NULL is what older versions printed for NOT_GRADED
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "PASS" => Ok(GradeStatus::Pass),
+            "FAIL" => Ok(GradeStatus::Fail),
+            "KOMPLETTERING" | "KOMP" => Ok(GradeStatus::Komplettering),
+            "NOT_GRADED" | "NULL" => Ok(GradeStatus::NotGraded),
+            _ => Err(format!(
+                "unknown status \"{}\" (expected {})",
+                s,
+                GRADES.map(|g| g.as_str()).join(", ")
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for GradeStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<GradeStatus> for String {
+    fn from(status: GradeStatus) -> String {
+        status.as_str().to_string()
+    }
+}
+
+impl fmt::Display for GradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+//one problem the grader found in the submission
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GradeIssue {
+    pub file: String,
+    #[serde(default)]
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+//the JSON object the grader answers with, see OUTPUT_CONTRACT
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GradeResponse {
+    pub status: GradeStatus,
+    pub feedback: String,
+    #[serde(default)]
+    pub issues: Vec<GradeIssue>,
+    pub confidence: f64,
+}
+
+//a student that could not be graded, nothing is posted for them
+#[derive(Debug)]
+pub struct GradingFailed {
+    pub reason: String,
+    // every answer the model gave, kept so the TA can see what went wrong
+    pub answers: Vec<String>,
+    // the answers were paid for all the same
+    pub usage: Usage,
+}
+
+impl fmt::Display for GradingFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.answers.len() {
+            0 => write!(f, "{}", self.reason),
+            n => write!(f, "{} (after {} attempts)", self.reason, n),
+        }
+    }
+}
+
+impl std::error::Error for GradingFailed {}
+
+//Asks the provider and validates the answer. A malformed answer is sent back together with
+//what was wrong with it, up to max_attempts times in total. Transport errors are not retried here.
+//The usage covers every attempt.
+pub async fn grade(
+    provider: &dyn LlmProvider,
+    prompt: &str,
+    files: &[&str],
+    max_attempts: u32,
+) -> Result<(GradeResponse, Usage), GradingFailed> {
+    let mut answers = Vec::new();
+    let mut usage = Usage::default();
+    let mut request = prompt.to_string();
+    loop {
+        let started = Instant::now();
+        let answer = match provider.complete(&request).await {
+            Ok(completion) => {
+                usage.record(&request, &completion, started.elapsed());
+                completion.text
+            }
+            Err(e) => {
+                return Err(GradingFailed {
+                    reason: e.to_string(),
+                    answers,
+                    usage,
+                });
+            }
+        };
+        let result = parse_grade(&answer, files);
+        answers.push(answer);
+        match result {
+            Ok(grade) => return Ok((grade, usage)),
+            Err(reason) if answers.len() as u32 >= max_attempts.max(1) => {
+                return Err(GradingFailed {
+                    reason: format!("malformed grader output: {}", reason),
+                    answers,
+                    usage,
+                });
+            }
+            Err(reason) => {
+                println!(
+                    "   \x1b[33m↻ Malformed answer ({}), asking again...\x1b[0m",
+                    reason
+                );
+                request = format!(
+                    "{}\n\nYour previous answer was rejected: {}\nPrevious answer:\n{}\n\nAnswer again with ONLY the JSON object described in Output Format.",
+                    prompt,
+                    reason,
+                    answers.last().map(|a| a.as_str()).unwrap_or_default()
+                );
+            }
+        }
+    }
+}
+
+//The files an issue can point at: the submitted files, the student's tests and the test files
+//named in the test output (stack frames, compiler errors)
+pub fn referable_files(payload: &Payload) -> Vec<String> {
+    let mut files: Vec<String> = payload
+        .source_files
+        .iter()
+        .chain(&payload.student_tests)
+        .map(|f| f.filename.clone())
+        .collect();
+    let named = test_output(payload)
+        .split(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '$' | '.' | '/' | '-')))
+        .filter(|word| word.len() > ".java".len() && word.ends_with(".java"));
+    for name in named {
+        if !files.iter().any(|f| f == name) {
+            files.push(name.to_string());
+        }
+    }
+    files
+}
+
+//Parses and validates the grader's answer against the contract.
+//files are the ones from referable_files, issues have to point at one of them.
+pub fn parse_grade(answer: &str, files: &[&str]) -> Result<GradeResponse, String> {
+    let json = extract_json_object(answer).ok_or("the answer contains no JSON object")?;
+    let grade: GradeResponse =
+        serde_json::from_str(json).map_err(|e| format!("invalid JSON object: {}", e))?;
+
+    if !grade.status.is_graded() {
+        return Err(format!(
+            "\"status\" must be one of {}, got {}",
+            GRADES.map(|g| g.as_str()).join(", "),
+            grade.status
+        ));
+    }
+    if grade.feedback.trim().is_empty() {
+        return Err("\"feedback\" is empty".to_string());
+    }
+    if !(0.0..=1.0).contains(&grade.confidence) {
+        return Err(format!(
+            "\"confidence\" must be between 0 and 1, got {}",
+            grade.confidence
+        ));
+    }
+    for issue in &grade.issues {
+        if !files.is_empty() && !files.contains(&issue.file.as_str()) {
+            return Err(format!(
+                "issue refers to \"{}\" which is not one of the submitted or test files ({})",
+                issue.file,
+                files.join(", ")
+            ));
+        }
+        if issue.line == Some(0) {
+            return Err("issue line numbers start at 1".to_string());
+        }
+        if issue.message.trim().is_empty() {
+            return Err("issue without a \"message\"".to_string());
+        }
+    }
+    Ok(grade)
+}
+
+// Models like to wrap JSON in ```json fences or add a sentence around it,
+// the object itself is everything from the first { to the last }.
+fn extract_json_object(answer: &str) -> Option<&str> {
+    let start = answer.find('{')?;
+    let end = answer.rfind('}')?;
+    (start < end).then(|| &answer[start..=end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_parser::SourceFile;
+
+    fn payload() -> Payload {
+        let file = |name: &str| SourceFile {
+            filename: name.to_string(),
+            content: String::new(),
+        };
+        Payload {
+            user_id: "alice".to_string(),
+            task: "task-1".to_string(),
+            read_me: String::new(),
+            source_files: vec![file("Calc.java")],
+            student_tests: vec![file("MyCalcTest.java")],
+            test_results: String::new(),
+            test_summary: "[FAIL] CalcTest.testAdd\n    at CalcTest.testAdd(CalcTest.java:12)\nsrc/Helper.java:3: error: ';' expected\n".to_string(),
+            test_report: None,
+            commit: None,
+            truncation: None,
+        }
+    }
+
+    fn answer(file: &str) -> String {
+        format!(
+            r#"{{"status": "KOMPLETTERING", "feedback": "Fix it", "issues": [{{"file": "{}", "line": 12, "message": "wrong sum"}}], "confidence": 0.7}}"#,
+            file
+        )
+    }
+
+    #[test]
+    fn referable_files_include_tests_and_files_in_the_output() {
+        assert_eq!(
+            referable_files(&payload()),
+            [
+                "Calc.java",
+                "MyCalcTest.java",
+                "CalcTest.java",
+                "src/Helper.java"
+            ]
+        );
+    }
+
+    #[test]
+    fn issues_may_point_at_test_files() {
+        let files = referable_files(&payload());
+        let files: Vec<&str> = files.iter().map(String::as_str).collect();
+        for file in ["Calc.java", "MyCalcTest.java", "CalcTest.java"] {
+            assert!(parse_grade(&answer(file), &files).is_ok(), "{}", file);
+        }
+        let err = parse_grade(&answer("Other.java"), &files).unwrap_err();
+        assert!(err.contains("Other.java"), "{}", err);
+    }
+
+    #[test]
+    fn statuses_parse_in_any_case_and_short_forms() {
+        assert_eq!("PASS".parse(), Ok(GradeStatus::Pass));
+        assert_eq!(" fail ".parse(), Ok(GradeStatus::Fail));
+        assert_eq!("Komplettering".parse(), Ok(GradeStatus::Komplettering));
+        assert_eq!("komp".parse(), Ok(GradeStatus::Komplettering));
+        assert_eq!("NOT_GRADED".parse(), Ok(GradeStatus::NotGraded));
+        assert_eq!("null".parse(), Ok(GradeStatus::NotGraded));
+        assert!("PASSED".parse::<GradeStatus>().is_err());
+        assert!("".parse::<GradeStatus>().is_err());
+    }
+
+    #[test]
+    fn issue_titles_start_with_the_canonical_status() {
+        assert_eq!(
+            GradeStatus::from_issue_title("PASS"),
+            Some(GradeStatus::Pass)
+        );
+        assert_eq!(
+            GradeStatus::from_issue_title(" KOMPLETTERING: fix the tests"),
+            Some(GradeStatus::Komplettering)
+        );
+        assert_eq!(
+            GradeStatus::from_issue_title("[FAIL] task-3"),
+            Some(GradeStatus::Fail)
+        );
+        assert_eq!(GradeStatus::from_issue_title("PASSWORD reset"), None);
+        assert_eq!(GradeStatus::from_issue_title("Password reset"), None);
+        assert_eq!(GradeStatus::from_issue_title("FAILING build"), None);
+    }
+
+    #[test]
+    fn student_issue_titles_have_no_status() {
+        for title in [
+            "how do I pass test 3",
+            "pass",
+            "Task 5: komp",
+            "Test 2 FAIL on my machine",
+            "Why KOMPLETTERING?",
+            "[pass] done",
+        ] {
+            assert_eq!(GradeStatus::from_issue_title(title), None, "{}", title);
+        }
+        // NOT_GRADED is never read from a title
+        assert_eq!(GradeStatus::from_issue_title("NULL"), None);
+        assert_eq!(GradeStatus::from_issue_title("NOT_GRADED"), None);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::config::HttpConfig;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+pub type HttpError = Box<dyn std::error::Error + Send + Sync>;
+
+static CLIENT: OnceLock<HttpClient> = OnceLock::new();
+
+//A reqwest client with timeouts, retries with backoff and a limit on requests in flight.
+//Every network call of the crate goes through send.
+pub struct HttpClient {
+    client: Client,
+    config: HttpConfig,
+    limit: Semaphore,
+}
+
+//The client of this process, created from the first config it is asked with.
+//One client for everything, so http.max_concurrent covers the model APIs and GitHub together.
+pub fn http_client(config: &HttpConfig) -> &'static HttpClient {
+    CLIENT.get_or_init(|| HttpClient::new(config))
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpConfig) -> HttpClient {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .build()
+            // only fails if the TLS backend can not be set up, the plain client fails the same way later
+            .unwrap_or_default();
+        HttpClient {
+            client,
+            config: config.clone(),
+            limit: Semaphore::new(config.max_concurrent.max(1)),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    //Sends the request and retries timeouts, connection errors, 429, 5xx and rate limited 403s
+    //up to http.max_retries times. Retry-After and X-RateLimit-Reset say how long to wait,
+    //otherwise the backoff doubles on every attempt. `service` names the API in messages.
+    //The last response is returned as it is, checking the status is up to the caller.
+    pub async fn send(
+        &self,
+        request: RequestBuilder,
+        service: &str,
+    ) -> Result<Response, HttpError> {
+        self.send_with_retries(request, service, true).await
+    }
+
+    //For requests that must not happen twice, like creating an issue. After a timeout or a 5xx
+    //the server may have done it anyway, so only requests it certainly did not act on are
+    //retried: 429, rate limited 403s and connections that failed before anything was sent.
+    pub async fn send_once(
+        &self,
+        request: RequestBuilder,
+        service: &str,
+    ) -> Result<Response, HttpError> {
+        self.send_with_retries(request, service, false).await
+    }
+
+    async fn send_with_retries(
+        &self,
+        request: RequestBuilder,
+        service: &str,
+        idempotent: bool,
+    ) -> Result<Response, HttpError> {
+        let mut attempt = 0;
+        loop {
+            let retry = request
+                .try_clone()
+                .ok_or("a streaming request body can not be retried")?;
+            let result = {
+                let _permit = self.limit.acquire().await?;
+                retry.send().await
+            };
+            let (reason, wait) = match &result {
+                Ok(response) => match retry_reason(response) {
+                    Some(reason) if idempotent || !response.status().is_server_error() => {
+                        (reason, header_wait(response.headers()))
+                    }
+                    _ => return Ok(result?),
+                },
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => (
+                    if e.is_timeout() {
+                        "timeout"
+                    } else {
+                        "connection failed"
+                    }
+                    .to_string(),
+                    None,
+                ),
+                Err(_) => return Ok(result?),
+            };
+            if attempt >= self.config.max_retries {
+                return Ok(result?);
+            }
+            if let Some(wait) = wait
+                && wait > Duration::from_secs(self.config.max_wait_secs)
+            {
+                println!(
+                    "   \x1b[33m⏳ {}: {}, the server asks to wait {}s, more than http.max_wait_secs\x1b[0m",
+                    service,
+                    reason,
+                    wait.as_secs()
+                );
+                return Ok(result?);
+            }
+            let wait = wait.unwrap_or_else(|| self.backoff(attempt));
+            attempt += 1;
+            println!(
+                "   \x1b[33m↻ {}: {}, retrying in {:.1}s ({}/{})\x1b[0m",
+                service,
+                reason,
+                wait.as_secs_f64(),
+                attempt,
+                self.config.max_retries
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // initial_backoff * 2^attempt, capped, the second half is random so clients do not retry in lockstep
+    fn backoff(&self, attempt: u32) -> Duration {
+        let max = self.config.max_backoff_secs.saturating_mul(1000);
+        let base = self
+            .config
+            .initial_backoff_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(max)
+            .max(1);
+        let jitter = RandomState::new().build_hasher().finish() % (base / 2 + 1);
+        Duration::from_millis(base / 2 + jitter)
+    }
+}
+
+// Why a response is worth another try, None if it is final
+fn retry_reason(response: &Response) -> Option<String> {
+    let status = response.status();
+    let rate_limited = status == StatusCode::FORBIDDEN
+        && (response.headers().contains_key(RETRY_AFTER)
+            || header(response.headers(), "x-ratelimit-remaining") == Some("0"));
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => Some("rate limited (429)".to_string()),
+        // GitHub answers 403 for both its primary and secondary rate limits
+        _ if rate_limited => Some("rate limited (403)".to_string()),
+        _ if status.is_server_error() => Some(format!("server error ({})", status)),
+        _ => None,
+    }
+}
+
+// How long the server asked us to wait: Retry-After (seconds or an HTTP date),
+// or GitHub's X-RateLimit-Reset (epoch seconds) once the limit is used up.
+fn header_wait(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(value) = header(headers, RETRY_AFTER.as_str()) {
+        if let Ok(secs) = value.trim().parse::<u64>() {
+            return Some(Duration::from_secs(secs));
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(until(date.timestamp()));
+        }
+    }
+    if header(headers, "x-ratelimit-remaining") == Some("0") {
+        let reset = header(headers, "x-ratelimit-reset")?
+            .trim()
+            .parse::<i64>()
+            .ok()?;
+        // one second extra, the reset time is rounded down
+        return Some(until(reset) + Duration::from_secs(1));
+    }
+    None
+}
+
+fn until(timestamp: i64) -> Duration {
+    Duration::from_secs((timestamp - Utc::now().timestamp()).max(0) as u64)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Answers the n-th request with statuses[n] (the last one from then on), returns the base URL
+    async fn server(statuses: &'static [u16], requests: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let n = requests.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[n.min(statuses.len() - 1)];
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    fn client() -> HttpClient {
+        HttpClient::new(&HttpConfig {
+            initial_backoff_ms: 1,
+            max_backoff_secs: 1,
+            ..HttpConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let url = server(&[503, 502, 200], requests.clone()).await;
+        let client = client();
+        let response = client.send(client.post(&url), "test").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn send_once_does_not_retry_server_errors() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let url = server(&[502, 200], requests.clone()).await;
+        let client = client();
+        let response = client.send_once(client.post(&url), "test").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn send_once_retries_rate_limits() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let url = server(&[429, 200], requests.clone()).await;
+        let client = client();
+        let response = client.send_once(client.post(&url), "test").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
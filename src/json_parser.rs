@@ -1,29 +1,90 @@
+use crate::grading::{GradeIssue, GradeResponse, GradeStatus};
+use crate::test_report::TestReport;
 use serde::{self, Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 //struct for payload that we use for AI api later
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Payload {
-    user_id: String,
-    task: String,
-    read_me: String,
-    source_files: Vec<SourceFile>,
-    test_results: String,
+    pub user_id: String,
+    pub task: String,
+    pub read_me: String,
+    pub source_files: Vec<SourceFile>,
+    // *Test.java files from the student's repo, kept apart from the graded source files
+    #[serde(default)]
+    pub student_tests: Vec<SourceFile>,
+    pub test_results: String,
+    // plain-text rendering of test_report, this is what the AI prompt gets
+    #[serde(default)]
+    pub test_summary: String,
+    // parsed version of test_results, missing in payloads from older versions
+    #[serde(default)]
+    pub test_report: Option<TestReport>,
+    // commit that was compiled and tested, None if the checkout had no commit recorded
+    #[serde(default)]
+    pub commit: Option<String>,
+    // set when the payload was shortened to fit the prompt budget
+    #[serde(default)]
+    pub truncation: Option<Truncation>,
+}
+
+//what was left out of a payload that was over the prompt budget
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Truncation {
+    pub budget_tokens: usize,
+    // estimated tokens of the payload's texts before and after, the template comes on top
+    pub original_tokens: usize,
+    pub kept_tokens: usize,
+    // one line per shortened or dropped part, e.g. "Util.java: left out (3200 tokens)"
+    pub elided: Vec<String>,
+}
+
+//struct for one student's entry in src_paths.json
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SubmissionEntry {
+    pub src: PathBuf,
+    // commit the checkout was at when src_paths.json was written, the one that gets graded
+    pub commit: Option<String>,
+    // committer date of that commit (RFC3339)
+    #[serde(default)]
+    pub commit_time: Option<String>,
+    // commit recorded by the previous clone run, if there was one
+    pub previous_commit: Option<String>,
+    // true if the commit differs from the previous run, i.e. the student needs regrading
+    pub changed: bool,
+    // deadline the snapshot was taken for (RFC3339), None if the branch tip is graded
+    #[serde(default)]
+    pub deadline: Option<String>,
+    // commits made after the deadline that are not graded
+    #[serde(default)]
+    pub late_commits: usize,
 }
 
 //struct for students' files
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SourceFile {
-    filename: String,
-    content: String,
+    pub filename: String,
+    pub content: String,
 }
 
 //struct for ai grading
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FeedbackEntry {
     student_id: String,
-    status: String,
+    status: GradeStatus,
     feedback: String,
+    // missing in feedback files from older versions
+    #[serde(default)]
+    issues: Vec<GradeIssue>,
+    #[serde(default)]
+    confidence: Option<f64>,
+}
+
+//struct for a student generate could not grade, written to <output>/failed.json
+#[derive(Serialize, Deserialize)]
+pub struct FailedStudent {
+    pub student_id: String,
+    pub error: String,
 }
 
 //struct for sending github issue
@@ -36,7 +97,7 @@ pub struct Issue {
 #[derive(Serialize, Deserialize)]
 pub struct StatusIssue {
     pub studentid: String,
-    pub status: String,
+    pub status: GradeStatus,
 }
 //struct for using github API to get issues statuses
 #[derive(Serialize, Deserialize)]
@@ -56,19 +117,30 @@ pub struct IssueTitle {
 
 //Functions for creating our structs and parsing to JSON
 
-pub fn create_issue(title: String, body: String) -> Issue {
-    Issue { title, body }
+//the title is the bare status so check_issues can read it back
+pub fn create_issue(
+    status: GradeStatus,
+    body: String,
+) -> Result<Issue, Box<dyn std::error::Error>> {
+    if !status.is_graded() {
+        return Err(format!("Refusing to post a {} issue", status).into());
+    }
+    Ok(Issue {
+        title: status.to_string(),
+        body,
+    })
 }
 
 pub fn create_feedback_json(
     student_id: String,
-    status: String,
-    feedback: String,
+    grade: &GradeResponse,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let feedback = FeedbackEntry {
         student_id,
-        status,
-        feedback,
+        status: grade.status,
+        feedback: grade.feedback.clone(),
+        issues: grade.issues.clone(),
+        confidence: Some(grade.confidence),
     };
 
     let json_string = serde_json::to_string(&feedback)?;
@@ -76,26 +148,6 @@ pub fn create_feedback_json(
     Ok(json_string)
 }
 
-pub fn create_payload_json(
-    user_id: String,
-    task: String,
-    read_me: String,
-    source_files: Vec<SourceFile>,
-    test_results: String,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let payload = Payload {
-        user_id,
-        task,
-        read_me,
-        source_files,
-        test_results,
-    };
-
-    let json_string = serde_json::to_string(&payload)?;
-
-    Ok(json_string)
-}
-
 pub fn parse_source_file(
     filename: &str,
     content: &Path,
@@ -112,7 +164,8 @@ pub fn parse_source_file(
     Ok(source_file)
 }
 
-// Removes Java comments from code
+// Removes Java comments from code. The newlines of a block comment are kept, so line numbers
+// stay those of the student's file and of javac and stack traces.
 fn remove_comments(code: &str) -> String {
     let mut result = String::new();
     let mut chars = code.chars().peekable();
@@ -126,6 +179,8 @@ fn remove_comments(code: &str) -> String {
                 chars.next(); // consume the '/'
                 in_block_comment = false;
                 result.push(' '); // preserve spacing
+            } else if c == '\n' {
+                result.push(c);
             }
             continue;
         } else if in_line_comment {
@@ -173,11 +228,36 @@ fn remove_comments(code: &str) -> String {
 
 pub fn parse_issue_status(
     student: &str,
-    status: &str,
+    status: GradeStatus,
 ) -> Result<StatusIssue, Box<dyn std::error::Error>> {
     let status_issue = StatusIssue {
         studentid: student.to_string(),
-        status: status.to_string(),
+        status,
     };
     Ok(status_issue)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_keep_line_numbers() {
+        let code = "package deck;\n\n/**\n * A playing card.\n *\n * @author student\n */\npublic class Card { /* rank\n   and suit */ int rank; // 1-13\n    String suit = \"/* not a comment */\";\n    int value() { return rank; }\n}\n";
+        let stripped = remove_comments(code);
+        assert_eq!(stripped.lines().count(), code.lines().count());
+        let line_of = |text: &str, needle: &str| text.lines().position(|l| l.contains(needle));
+        for needle in ["public class Card", "int value()", "String suit"] {
+            assert_eq!(
+                line_of(&stripped, needle),
+                line_of(code, needle),
+                "{}",
+                needle
+            );
+        }
+        assert!(!stripped.contains("playing card"));
+        assert!(!stripped.contains("and suit"));
+        assert!(!stripped.contains("1-13"));
+        assert!(stripped.contains("\"/* not a comment */\""));
+    }
+}
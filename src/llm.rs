@@ -0,0 +1,430 @@
+use crate::config::{Config, GeminiConfig, LocalModelConfig, OpenAiConfig};
+use crate::http::{HttpClient, HttpError, http_client};
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use std::env;
+
+pub type LlmError = HttpError;
+
+//the model's answer and the tokens the provider billed for it
+pub struct Completion {
+    pub text: String,
+    // None if the server does not report usage (some local servers)
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+}
+
+//A model that turns a prompt into feedback. Implementations only do the HTTP part,
+//building the prompt and parsing the answer is shared.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    // shown in the terminal, e.g. "openai (gpt-4o-mini)"
+    fn name(&self) -> String;
+    // model name the prices are looked up with, e.g. "gpt-4o-mini"
+    fn model(&self) -> &str;
+    async fn complete(&self, prompt: &str) -> Result<Completion, LlmError>;
+}
+
+//OpenAI chat completions, also used for local servers that implement the same API
+pub struct OpenAiProvider {
+    client: &'static HttpClient,
+    // "openai" or "local:<name>"
+    label: String,
+    base_url: String,
+    model: String,
+    // None for local servers without authentication
+    api_key: Option<String>,
+}
+
+pub struct GeminiProvider {
+    client: &'static HttpClient,
+    config: GeminiConfig,
+    api_key: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(
+        client: &'static HttpClient,
+        config: &OpenAiConfig,
+    ) -> Result<OpenAiProvider, Box<dyn std::error::Error>> {
+        Ok(OpenAiProvider {
+            client,
+            label: "openai".to_string(),
+            base_url: config.base_url.clone(),
+            model: config.model.clone(),
+            api_key: Some(api_key(&config.api_key_env)?),
+        })
+    }
+
+    //a self-hosted server, student code never leaves the machine (or the department)
+    pub fn local(
+        client: &'static HttpClient,
+        name: &str,
+        config: &LocalModelConfig,
+    ) -> Result<OpenAiProvider, Box<dyn std::error::Error>> {
+        let api_key = match &config.api_key_env {
+            Some(var) => Some(api_key(var)?),
+            None => None,
+        };
+        Ok(OpenAiProvider {
+            client,
+            label: format!("local:{}", name),
+            base_url: config.base_url.clone(),
+            model: config.model.clone().unwrap_or_else(|| name.to_string()),
+            api_key,
+        })
+    }
+}
+
+impl GeminiProvider {
+    pub fn new(
+        client: &'static HttpClient,
+        config: &GeminiConfig,
+    ) -> Result<GeminiProvider, Box<dyn std::error::Error>> {
+        Ok(GeminiProvider {
+            client,
+            api_key: api_key(&config.api_key_env)?,
+            config: config.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> String {
+        format!("{} ({})", self.label, self.model)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, prompt: &str) -> Result<Completion, LlmError> {
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let body = json!({
+            "model": self.model,
+            "messages": [{"role": "user", "content": prompt}],
+            "response_format": {"type": "json_object"},
+        });
+        let mut request = self.client.post(&url).json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = self
+            .client
+            .send(request, &self.label)
+            .await
+            .map_err(|e| format!("Could not reach {} at {}: {}", self.label, url, e))?;
+        let value = json_response(response, &self.label).await?;
+        let text = value["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| format!("{} response has no message content: {}", self.label, value))?;
+        Ok(Completion {
+            text: text.to_string(),
+            prompt_tokens: value["usage"]["prompt_tokens"].as_u64(),
+            completion_tokens: value["usage"]["completion_tokens"].as_u64(),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    fn name(&self) -> String {
+        format!("gemini ({})", self.config.model)
+    }
+
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    async fn complete(&self, prompt: &str) -> Result<Completion, LlmError> {
+        let url = format!(
+            "{}/models/{}:generateContent",
+            self.config.base_url.trim_end_matches('/'),
+            self.config.model
+        );
+        let body = json!({
+            "contents": [{"parts": [{"text": prompt}]}],
+            "generationConfig": {"responseMimeType": "application/json"},
+        });
+        let request = self
+            .client
+            .post(&url)
+            .header("x-goog-api-key", &self.api_key)
+            .json(&body);
+        let response = self
+            .client
+            .send(request, "gemini")
+            .await
+            .map_err(|e| format!("Could not reach Gemini at {}: {}", url, e))?;
+        let value = json_response(response, "Gemini").await?;
+        // The answer can be split over several parts
+        let parts = value["candidates"][0]["content"]["parts"]
+            .as_array()
+            .ok_or_else(|| format!("Gemini response has no content: {}", value))?;
+        let usage = &value["usageMetadata"];
+        Ok(Completion {
+            text: parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect::<String>(),
+            prompt_tokens: usage["promptTokenCount"].as_u64(),
+            // thinking models bill their thoughts as output as well
+            completion_tokens: usage["candidatesTokenCount"]
+                .as_u64()
+                .map(|n| n + usage["thoughtsTokenCount"].as_u64().unwrap_or(0)),
+        })
+    }
+}
+
+//Picks the provider by name ("openai", "gemini" or "local:<name>"), None means llm.provider from the config.
+//A local name without an [llm.local.<name>] entry is served by Ollama on this machine.
+pub fn provider_from_config(
+    config: &Config,
+    name: Option<&str>,
+) -> Result<Box<dyn LlmProvider>, Box<dyn std::error::Error>> {
+    let name = name.unwrap_or(&config.llm.provider);
+    let client = http_client(&config.http);
+    match name {
+        "openai" => Ok(Box::new(OpenAiProvider::new(client, &config.llm.openai)?)),
+        "gemini" => Ok(Box::new(GeminiProvider::new(client, &config.llm.gemini)?)),
+        other => {
+            let local = parse_provider(other)?
+                .ok_or_else(|| format!("Missing model name in '{}'", other))?;
+            let entry = config.llm.local.get(local).cloned().unwrap_or_default();
+            Ok(Box::new(OpenAiProvider::local(client, local, &entry)?))
+        }
+    }
+}
+
+//The largest prompt (in estimated tokens) for the provider, max_prompt_tokens of its config section.
+//Needs no API key, so payloads can be fitted before anything is sent.
+pub fn prompt_budget(config: &Config, name: Option<&str>) -> Result<usize, String> {
+    let name = name.unwrap_or(&config.llm.provider);
+    Ok(match parse_provider(name)? {
+        Some(local) => {
+            config
+                .llm
+                .local
+                .get(local)
+                .cloned()
+                .unwrap_or_default()
+                .max_prompt_tokens
+        }
+        None if name == "gemini" => config.llm.gemini.max_prompt_tokens,
+        None => config.llm.openai.max_prompt_tokens,
+    })
+}
+
+//Checks a provider name, returns the <name> of local:<name>. Also the value_parser for `generate -m`.
+pub fn parse_provider(name: &str) -> Result<Option<&str>, String> {
+    match name {
+        "openai" | "gemini" => Ok(None),
+        _ => match name.strip_prefix("local:") {
+            Some(local) if !local.is_empty() => Ok(Some(local)),
+            _ => Err(format!(
+                "unknown provider '{}' (expected openai, gemini or local:<name>)",
+                name
+            )),
+        },
+    }
+}
+
+fn api_key(var: &str) -> Result<String, Box<dyn std::error::Error>> {
+    env::var(var).map_err(|_| format!("Missing {} environment variable", var).into())
+}
+
+async fn json_response(response: reqwest::Response, service: &str) -> Result<Value, LlmError> {
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(format!("{} API error ({}): {}", service, status, text).into());
+    }
+    Ok(response.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HttpConfig;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    //what the stub server received: request line, lowercase headers and the JSON body
+    #[derive(Default)]
+    struct Received {
+        request_line: String,
+        headers: Vec<(String, String)>,
+        body: Value,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    // Answers every request with `status` and `answer`, returns the base URL
+    async fn stub(status: u16, answer: Value) -> (String, Arc<Mutex<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Received::default()));
+        let seen = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut data = Vec::new();
+                let mut buf = [0u8; 4096];
+                // the headers, then as much body as content-length says
+                let (head_end, length) = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&data);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        break (end + 4, length);
+                    }
+                };
+                while data.len() < head_end + length {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                }
+                let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+                let mut lines = head.lines();
+                *seen.lock().unwrap() = Received {
+                    request_line: lines.next().unwrap_or_default().to_string(),
+                    headers: lines
+                        .filter_map(|l| l.split_once(':'))
+                        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+                        .collect(),
+                    body: serde_json::from_slice(&data[head_end..]).unwrap_or_default(),
+                };
+                let body = answer.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    fn client() -> &'static HttpClient {
+        Box::leak(Box::new(HttpClient::new(&HttpConfig {
+            max_retries: 0,
+            ..HttpConfig::default()
+        })))
+    }
+
+    fn openai(base_url: String, api_key: Option<&str>) -> OpenAiProvider {
+        OpenAiProvider {
+            client: client(),
+            label: "openai".to_string(),
+            base_url,
+            model: "gpt-test".to_string(),
+            api_key: api_key.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn openai_sends_the_prompt_and_reads_the_answer() {
+        let (url, received) = stub(
+            200,
+            json!({
+                "choices": [{"message": {"content": "{\"status\": \"PASS\"}"}}],
+                "usage": {"prompt_tokens": 120, "completion_tokens": 8}
+            }),
+        )
+        .await;
+        let completion = openai(url, Some("sk-test"))
+            .complete("grade this")
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "{\"status\": \"PASS\"}");
+        assert_eq!(completion.prompt_tokens, Some(120));
+        assert_eq!(completion.completion_tokens, Some(8));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.request_line, "POST /v1/chat/completions HTTP/1.1");
+        assert_eq!(received.header("authorization"), Some("Bearer sk-test"));
+        assert_eq!(received.body["model"], "gpt-test");
+        assert_eq!(received.body["messages"][0]["role"], "user");
+        assert_eq!(received.body["messages"][0]["content"], "grade this");
+        assert_eq!(received.body["response_format"]["type"], "json_object");
+    }
+
+    #[tokio::test]
+    async fn a_local_server_without_key_or_usage() {
+        let (url, received) = stub(200, json!({"choices": [{"message": {"content": "{}"}}]})).await;
+        let completion = openai(url, None).complete("grade this").await.unwrap();
+        assert_eq!(completion.text, "{}");
+        assert_eq!(completion.prompt_tokens, None);
+        assert_eq!(completion.completion_tokens, None);
+        assert_eq!(received.lock().unwrap().header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn gemini_sends_the_prompt_and_joins_the_parts() {
+        let (url, received) = stub(
+            200,
+            json!({
+                "candidates": [{"content": {"parts": [{"text": "{\"status\":"}, {"text": " \"FAIL\"}"}]}}],
+                "usageMetadata": {"promptTokenCount": 200, "candidatesTokenCount": 10, "thoughtsTokenCount": 30}
+            }),
+        )
+        .await;
+        let provider = GeminiProvider {
+            client: client(),
+            config: GeminiConfig {
+                base_url: url,
+                model: "gemini-test".to_string(),
+                ..GeminiConfig::default()
+            },
+            api_key: "g-key".to_string(),
+        };
+        let completion = provider.complete("grade this").await.unwrap();
+        assert_eq!(completion.text, "{\"status\": \"FAIL\"}");
+        assert_eq!(completion.prompt_tokens, Some(200));
+        // thoughts are billed as output
+        assert_eq!(completion.completion_tokens, Some(40));
+
+        let received = received.lock().unwrap();
+        assert_eq!(
+            received.request_line,
+            "POST /v1/models/gemini-test:generateContent HTTP/1.1"
+        );
+        assert_eq!(received.header("x-goog-api-key"), Some("g-key"));
+        assert_eq!(
+            received.body["contents"][0]["parts"][0]["text"],
+            "grade this"
+        );
+        assert_eq!(
+            received.body["generationConfig"]["responseMimeType"],
+            "application/json"
+        );
+    }
+
+    #[tokio::test]
+    async fn an_error_status_is_an_error() {
+        let (url, _) = stub(401, json!({"error": "bad key"})).await;
+        let Err(err) = openai(url, Some("wrong")).complete("grade this").await else {
+            panic!("a 401 was taken as an answer");
+        };
+        let err = err.to_string();
+        assert!(err.contains("401") && err.contains("bad key"), "{}", err);
+    }
+}
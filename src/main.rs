@@ -1,13 +1,38 @@
+mod budget;
+mod cache;
+mod config;
+mod editor;
+mod git_ops;
 mod github_api;
+mod grading;
+mod http;
 mod json_parser;
+mod llm;
+mod outbox;
+mod process;
+mod prompt;
+mod pseudonym;
+mod review;
+mod roster;
+mod test_report;
+mod tui;
+mod usage;
 use std::path::PathBuf;
 
+use chrono::{DateTime, FixedOffset};
 use clap::{Parser, Subcommand};
 
+use crate::config::{Config, ConfigOptions, show_config};
 use crate::github_api::{
     check_issues, clone_repos, create_payload, get_tests, print_feedback, print_test_results,
-    send_payload,
+    resolve_unittest_dir, send_payload,
 };
+use crate::llm::{parse_provider, prompt_budget};
+use crate::prompt::print_prompt;
+use crate::review::{PostPolicy, review_queue};
+use crate::roster::parse_deadline;
+use crate::tui::run_tui;
+use crate::usage::print_ledger;
 
 #[derive(Parser)]
 #[command(
@@ -18,51 +43,114 @@ use crate::github_api::{
     Available commands:\n\
       help      - Show this help message.\n\
       clone     - Clone student repositories and optionally compile/test Java files.\n\
-      tests     - Clone all solution repos from inda-master into a specified output directory.\n\
+      tests     - Clone or update the solution repos of the task registry.\n\
       results   - Print test results from JSON file(s) in a clear terminal format.\n\
-      generate - Send JSON payloads to the Python AI API for grading and post feedback to GitHub.\n\
+      generate  - Send JSON payloads to OpenAI or Gemini for grading and post feedback to GitHub.\n\
+      review    - Review pending feedback (approve, edit in $EDITOR, change status, skip, reject) and post it.\n\
+      tui       - Full-screen grading session: students, tests, source and feedback side by side.\n\
       feedback  - Print AI-generated feedback from JSON file(s) in a clear terminal format.\n\
-      issues    - Check GitHub issues for students and display their status (PASS, FAIL, KOMP, KOMPLETTERING).\n\
+      issues    - Check GitHub issues for students and display their status (PASS, FAIL, KOMPLETTERING or NOT_GRADED).\n\
+      prompt    - Print the prompt generate would send for a payload (imagi prompt render).\n\
+      usage     - Show tokens and cost per task from the usage ledger.\n\
+      config    - Show the effective configuration and where each value came from.\n\
     \n\
     USAGE EXAMPLES:\n\
       imagi help\n\
         Show this help message.\n\
     \n\
-      imagi clone -s/--students <path-to-students.txt> -t/--task <task-number> -o/--output <output-dir> -u/--unittest <solutions-src-dir>\n\
+      imagi clone -s/--students <path-to-students.txt> -t/--task <task-number> -o/--output <output-dir> [-u/--unittest <solutions-src-dir>]\n\
         Clone all student repos for the specified task into <output-dir>/<task-number>, creates src_paths.json and compiles/tests Java files.\n\
         The unittest parameter specifies the directory containing test files for compilation and testing.\n\
+        Without it, the tests are looked up in solutions.dir using the task registry in the config.\n\
+        Running clone again fetches and updates existing checkouts (clone.update = \"fast-forward\" or \"reset\").\n\
+        Add --changed-only to only compile/test students whose commit changed since the last run.\n\
+        Add --deadline <RFC3339> to grade the last commit before the deadline, e.g. --deadline 2025-10-17T23:59:00+02:00.\n\
+        Tests run in a temporary build directory, add --keep-build to keep it for debugging.\n\
+        Students are compiled/tested in parallel, --jobs N sets how many at once (default: number of CPUs).\n\
+        javac and java are killed after java.compile_timeout_secs / java.test_timeout_secs (TIMED OUT)\n\
+        and limited to java.max_heap / java.max_memory_mb (OUT OF MEMORY).\n\
         Example:\n\
           imagi clone -s /home/inda-25-students.txt -t task-5 -o /home/inda-25 -u /home/inda-master/task-5/src\n\
         Example students.txt file:\n\
           alice\n\
           bob\n\
-          charlie\n\
-          # Each line should contain a student kth_ID.\n\
+          charlie 2025-10-24T23:59:00+02:00\n\
+          # Each line should contain a student kth_ID, optionally followed by a deadline extension.\n\
     \n\
-      imagi tests -o/--output <output-dir>\n\
-        Clone all solution repos from inda-master for all tasks into <output-dir>.\n\
+      imagi tests [-o/--output <output-dir>] [-t/--task <task>]...\n\
+        Clone or update the solution repos of the task registry into <output-dir> (default: solutions.dir).\n\
+        Use --task (repeatable) to only fetch some tasks, e.g. imagi tests -t task-5 -t quicksort.\n\
     \n\
       imagi results -j/--json <path-to-json-or-dir>\n\
         Print test results from a JSON file or directory in a readable format.\n\
+        Shows totals, failing tests with their message and stack trace, and javac errors.\n\
     \n\
       imagi generate -j/--json <json-dir> -o/--output <output-dir> [-m/--model <openai|gemini>]\n\
-        Send JSON payloads to the Python AI API for grading and post feedback to GitHub.\n\
-        Default model is 'openai'. If using 'gemini', a Python virtual environment must be set up.\n\
+        Send JSON payloads to OpenAI or Gemini for grading and post feedback to GitHub.\n\
+        Default model is llm.provider from the config ('openai'). Base URLs and models are set under [llm].\n\
+        Use -m local:<name> for a self-hosted OpenAI-compatible server ([llm.local.<name>], default: Ollama).\n\
+        Add --non-interactive [--post-policy <never|pass-only|all|status-matches-tests>] to grade without prompts:\n\
+        issues allowed by the policy (default: never) are posted, the rest are queued in <output-dir>/review.\n\
+        Every graded student is saved in the review queue first, quitting with [q] keeps grading the rest.\n\
+        Answers are cached in <output-dir>/cache, reruns skip students that are already graded (--force regrades).\n\
+        A student that fails is recorded in <output-dir>/failed.json and the run continues.\n\
+        Rate limits, timeouts and server errors are retried with backoff, see [http] in the config.\n\
+        Add --prompt <student|teacher|path> to pick the prompt template (default: llm.prompt).\n\
+        Payloads over the model's max_prompt_tokens are shortened, failing-test output and the files it names first.\n\
+        Tokens, latency and cost per student are saved in <output-dir>/usage.json and summed up at the end,\n\
+        every run is appended to the usage ledger (see imagi usage). Prices are set under [llm.prices].\n\
+    \n\
+      imagi review -o/--output <output-dir>\n\
+        Walk the pending feedback in <output-dir>/review: approve and post, edit in $VISUAL/$EDITOR, change the status,\n\
+        skip or reject. Decisions are saved right away, so reviewing can be stopped and resumed.\n\
+    \n\
+      imagi tui -j/--json <json-dir> -o/--output <output-dir>\n\
+        Full-screen version of review: student list, source files, AI feedback and test results side by side.\n\
+        Keys: arrows select student/file, Tab/PgUp/PgDn scroll, a approve, e edit, s status, n next,\n\
+        r reject, u undo, p post the approved issues, q quit.\n\
     \n\
       imagi feedback -j/--json <path-to-feedback-json-or-dir>\n\
         Print AI-generated feedback from a JSON file or directory in a readable format.\n\
     \n\
       imagi issues -s/--students <path-to-students.txt> -t/--task <task>\n\
-        Check GitHub issues for all students in a task and display their status (PASS, FAIL, KOMP, KOMPLETTERING).\n\
+        Check GitHub issues for all students in a task and display their status (PASS, FAIL, KOMPLETTERING or NOT_GRADED).\n\
         Shows a formatted table with student names and their issue status with corresponding emojis.\n\
+        The status is read from any word of the issue title, so KOMP is recognised as KOMPLETTERING.\n\
+    \n\
+      imagi prompt render <payload.json> [--prompt <student|teacher|path>] [-m/--model <provider>]\n\
+        Print the prompt generate would send for one payload, which template it came from and what was left out\n\
+        to fit the model's prompt budget.\n\
+        Templates use {{readme}}, {{files}}, {{filenames}}, {{student_tests}}, {{test_results}}, {{rubric}} and {{task}}.\n\
+        llm.prompt_dir overrides the built-in templates per course (<dir>/student.txt) and per task (<dir>/<task>/student.txt).\n\
+    \n\
+      imagi usage\n\
+        Sum up the usage ledger (usage.ledger) per task and model: runs, students, tokens and cost.\n\
+    \n\
+      imagi config show\n\
+        Print the merged configuration (defaults, ~/.config/imagi/config.toml, imagi.toml, IMAGI_* env vars, --set).\n\
+    \n\
+    Global options:\n\
+      --config <path>      Use this course config file instead of looking for imagi.toml.\n\
+      --set <key=value>    Override a single config key, e.g. --set forge.student_org=inda-26.\n\
+      --dry-run            Write every GitHub issue to the outbox (dry_run.outbox) instead of posting it.\n\
+                           Cloning, tests and grading run as usual.\n\
     \n\
     Notes:\n\
       - All commands that clone or generate files require an explicit --output directory.\n\
       - Output directories will be created automatically if they do not exist.\n\
-      - The 'generate' command integrates with the Python AI API and posts feedback to GitHub issues.\n\
+      - The 'generate' command calls the OpenAI or Gemini API directly and posts feedback to GitHub issues.\n\
+      - Student IDs, names and emails are replaced with pseudonyms before a payload is sent to the model\n\
+        and put back into the feedback (privacy.* in the config).\n\
       "
 )]
 struct Cli {
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+    // same as --set dry_run.enabled=true
+    #[arg(long, global = true)]
+    dry_run: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -76,12 +164,23 @@ enum Commands {
         task: String,
         #[arg(short = 'o', long)]
         output: PathBuf,
-        #[arg(short = 'u', long = "unittest", required = true)]
-        tests: PathBuf,
+        #[arg(short = 'u', long = "unittest")]
+        tests: Option<PathBuf>,
+        #[arg(long)]
+        changed_only: bool,
+        #[arg(long, value_parser = parse_deadline)]
+        deadline: Option<DateTime<FixedOffset>>,
+        #[arg(long)]
+        keep_build: bool,
+        // compile/test this many students at once, defaults to the number of CPUs
+        #[arg(short = 'j', long)]
+        jobs: Option<usize>,
     },
     Tests {
         #[arg(short = 'o', long)]
-        output: PathBuf,
+        output: Option<PathBuf>,
+        #[arg(short = 't', long = "task")]
+        tasks: Vec<String>,
     },
     Results {
         #[arg(short = 'j', long)]
@@ -92,8 +191,31 @@ enum Commands {
         json: PathBuf,
         #[arg(short = 'o', long)]
         output: PathBuf,
-        #[arg(short = 'm', long, default_value = "openai", value_parser = ["openai", "gemini"])]
-        model: String,
+        // openai, gemini or local:<name>, defaults to llm.provider from the config
+        #[arg(short = 'm', long, value_parser = parse_model)]
+        model: Option<String>,
+        // grade everything without prompts, --post-policy decides what is posted
+        #[arg(long)]
+        non_interactive: bool,
+        #[arg(long, default_value = "never", requires = "non_interactive")]
+        post_policy: PostPolicy,
+        // grade again even if the cache has an answer for the same payload, prompt and model
+        #[arg(long)]
+        force: bool,
+        // student, teacher or a template file, defaults to llm.prompt from the config
+        #[arg(long)]
+        prompt: Option<String>,
+    },
+    Review {
+        // the output directory of generate, the one that contains review/
+        #[arg(short = 'o', long)]
+        output: PathBuf,
+    },
+    Tui {
+        #[arg(short = 'j', long)]
+        json: PathBuf,
+        #[arg(short = 'o', long)]
+        output: PathBuf,
     },
     Feedback {
         #[arg(short = 'j', long)]
@@ -105,14 +227,66 @@ enum Commands {
         #[arg(short = 't', long)]
         task: String,
     },
+    Prompt {
+        #[command(subcommand)]
+        action: PromptAction,
+    },
+    // token usage and cost per task from the usage ledger
+    Usage,
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum PromptAction {
+    // print the prompt generate would send for one payload
+    Render {
+        payload: PathBuf,
+        #[arg(long)]
+        prompt: Option<String>,
+        // the prompt is shortened to this provider's budget, defaults to llm.provider
+        #[arg(short = 'm', long, value_parser = parse_model)]
+        model: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    Show,
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    let mut overrides = cli.overrides.clone();
+    if cli.dry_run {
+        overrides.push("dry_run.enabled=true".to_string());
+    }
+    let mut config = match Config::load(&ConfigOptions {
+        config_file: cli.config.clone(),
+        overrides,
+    }) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error while loading the configuration: {}", e);
+            return;
+        }
+    };
+    if config.dry_run.enabled {
+        println!(
+            "\x1b[1;35m🧪 Dry run: nothing is posted to GitHub, the requests go to {}\x1b[0m",
+            config.dry_run.outbox.display()
+        );
+    }
     match &cli.command {
-        Commands::Tests { output } => {
-            if let Err(e) = get_tests(output.to_path_buf()) {
+        Commands::Tests { output, tasks } => {
+            let Some(output) = output.clone().or_else(|| config.solutions.dir.clone()) else {
+                eprintln!("Error getting the tests: pass -o or set solutions.dir in the config");
+                return;
+            };
+            if let Err(e) = get_tests(&config, output, tasks) {
                 eprintln!("Error getting the tests: {}", e);
             }
         }
@@ -121,12 +295,34 @@ async fn main() {
             task,
             output,
             tests,
+            changed_only,
+            deadline,
+            keep_build,
+            jobs,
         } => {
+            // Tasks from the registry are cloned under their canonical name, e.g. "5" -> "task-5"
+            let task = config
+                .find_task(task)
+                .map(|t| t.name.clone())
+                .unwrap_or_else(|| task.to_string());
+            let tests = match tests {
+                Some(tests) => tests.to_path_buf(),
+                None => match resolve_unittest_dir(&config, &task) {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        eprintln!("Error while looking up the unit tests: {}", e);
+                        return;
+                    }
+                },
+            };
+
             // Clone repositories
             if let Err(e) = clone_repos(
+                &config,
                 students.to_path_buf(),
                 task.to_string(),
                 output.to_path_buf(),
+                *deadline,
             ) {
                 eprintln!("Error while cloning the repos or creating the json: {}", e);
                 return;
@@ -134,7 +330,7 @@ async fn main() {
 
             // Compile and test Java files after cloning
             // Construct the path to the generated src_paths.json
-            let repos_dir = output.join(&task);
+            let repos_dir = output.join(task);
             let json_path = repos_dir.join("src_paths.json");
             let compiled_output = repos_dir.join("compiled");
 
@@ -146,14 +342,17 @@ async fn main() {
 
             // Compile and test Java files
             if let Err(e) = create_payload(
+                &config,
                 json_path,
                 compiled_output,
-                tests.to_path_buf(),
-            ) {
-                eprintln!(
-                    "Error while compiling or running the java tests: {}",
-                    e
-                );
+                tests,
+                *changed_only,
+                *keep_build,
+                jobs.unwrap_or_else(default_jobs),
+            )
+            .await
+            {
+                eprintln!("Error while compiling or running the java tests: {}", e);
             } else {
                 println!("Successfully cloned repositories and compiled/tested Java files!");
             }
@@ -170,11 +369,35 @@ async fn main() {
             json,
             output,
             model,
+            non_interactive,
+            post_policy,
+            force,
+            prompt,
         } => {
-            if let Err(e) =
-                send_payload(json.to_path_buf(), output.to_path_buf(), Some(model)).await
+            if let Some(prompt) = prompt {
+                config.llm.prompt = prompt.clone();
+            }
+            if let Err(e) = send_payload(
+                &config,
+                json.to_path_buf(),
+                output.to_path_buf(),
+                model.as_deref(),
+                non_interactive.then_some(*post_policy),
+                *force,
+            )
+            .await
             {
-                eprint!("Error while grading the students: {}", e);
+                eprintln!("Error while grading the students: {}", e);
+            }
+        }
+        Commands::Review { output } => {
+            if let Err(e) = review_queue(&config, output.to_path_buf()).await {
+                eprintln!("Error while reviewing the feedback: {}", e);
+            }
+        }
+        Commands::Tui { json, output } => {
+            if let Err(e) = run_tui(&config, json.to_path_buf(), output.to_path_buf()).await {
+                eprintln!("Error in the grading TUI: {}", e);
             }
         }
         Commands::Feedback { json } => {
@@ -186,9 +409,49 @@ async fn main() {
             }
         }
         Commands::Issues { students, task } => {
-            if let Err(e) = check_issues(students.to_path_buf(), task.to_string()).await {
+            if let Err(e) = check_issues(&config, students.to_path_buf(), task.to_string()).await {
                 eprint!("Error while trying to get the issues statuses: {}", e);
             }
         }
+        Commands::Prompt { action } => match action {
+            PromptAction::Render {
+                payload,
+                prompt,
+                model,
+            } => {
+                if let Some(prompt) = prompt {
+                    config.llm.prompt = prompt.clone();
+                }
+                let result = prompt_budget(&config, model.as_deref())
+                    .map_err(|e| e.into())
+                    .and_then(|budget| print_prompt(&config, payload, budget));
+                if let Err(e) = result {
+                    eprintln!("Error while rendering the prompt: {}", e);
+                }
+            }
+        },
+        Commands::Usage => {
+            if let Err(e) = print_ledger(&config) {
+                eprintln!("Error while reading the usage ledger: {}", e);
+            }
+        }
+        Commands::Config { action } => match action {
+            ConfigAction::Show => {
+                if let Err(e) = show_config(&config) {
+                    eprintln!("Error while printing the configuration: {}", e);
+                }
+            }
+        },
     }
 }
+
+// One compile/test job per CPU, javac and the JVM are mostly CPU bound
+fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+fn parse_model(name: &str) -> Result<String, String> {
+    parse_provider(name).map(|_| name.to_string())
+}
@@ -0,0 +1,133 @@
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+//a GitHub request that was not sent because of --dry-run, one file in dry_run.outbox
+#[derive(Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub method: String,
+    pub url: String,
+    pub body: Value,
+    pub student_id: String,
+    pub task: String,
+    // RFC3339
+    pub created_at: String,
+}
+
+//Prints the request and writes it to the outbox as <nnnn>-<student>-<task>.json, numbered so
+//the files list in the order the requests would have been sent. A number is never reused, also
+//when entries were deleted or another run writes to the same outbox.
+pub fn record_request(
+    outbox: &Path,
+    method: &str,
+    url: &str,
+    body: Value,
+    student: &str,
+    task: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    println!(
+        "\n\x1b[1;35m🧪 DRY RUN: {} {}\x1b[0m\n{}",
+        method,
+        url,
+        serde_json::to_string_pretty(&body)?
+    );
+    fs::create_dir_all(outbox)
+        .map_err(|e| format!("Could not create the outbox {}: {}", outbox.display(), e))?;
+    let entry = OutboxEntry {
+        method: method.to_string(),
+        url: url.to_string(),
+        body,
+        student_id: student.to_string(),
+        task: task.to_string(),
+        created_at: Local::now().to_rfc3339(),
+    };
+    let (path, mut file) = create_numbered(outbox, last_number(outbox)? + 1, student, task)?;
+    file.write_all(serde_json::to_string_pretty(&entry)?.as_bytes())?;
+    println!(
+        "   \x1b[90m📤 Not sent, written to {}\x1b[0m",
+        path.display()
+    );
+    Ok(path)
+}
+
+// Creates the first free <nnnn>-<student>-<task>.json from `number` on. create_new, so a file
+// another run wrote in the meantime is never overwritten.
+fn create_numbered(
+    outbox: &Path,
+    mut number: u32,
+    student: &str,
+    task: &str,
+) -> io::Result<(PathBuf, File)> {
+    loop {
+        let path = outbox.join(format!("{:04}-{}-{}.json", number, student, task));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The highest <nnnn> prefix of the outbox files, 0 for an empty outbox
+fn last_number(outbox: &Path) -> io::Result<u32> {
+    Ok(fs::read_dir(outbox)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.ends_with(".json")
+                .then(|| name.split('-').next()?.parse().ok())
+                .flatten()
+        })
+        .max()
+        .unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(outbox: &Path, student: &str) -> String {
+        let path = record_request(
+            outbox,
+            "POST",
+            "https://example.invalid/issues",
+            serde_json::json!({ "title": "PASS" }),
+            student,
+            "task-1",
+        )
+        .unwrap();
+        path.file_name().unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn numbers_are_never_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let outbox = dir.path().join("outbox");
+        assert_eq!(record(&outbox, "alice"), "0001-alice-task-1.json");
+        assert_eq!(record(&outbox, "bob"), "0002-bob-task-1.json");
+        assert_eq!(record(&outbox, "carol"), "0003-carol-task-1.json");
+        fs::remove_file(outbox.join("0001-alice-task-1.json")).unwrap();
+        assert_eq!(record(&outbox, "dave"), "0004-dave-task-1.json");
+        fs::write(outbox.join("notes.txt"), "").unwrap();
+        assert_eq!(record(&outbox, "erin"), "0005-erin-task-1.json");
+        let entry: OutboxEntry =
+            serde_json::from_str(&fs::read_to_string(outbox.join("0002-bob-task-1.json")).unwrap())
+                .unwrap();
+        assert_eq!(entry.student_id, "bob");
+    }
+
+    #[test]
+    fn a_number_taken_in_the_meantime_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("0007-alice-task-1.json"), "{}").unwrap();
+        let (path, _) = create_numbered(dir.path(), 7, "alice", "task-1").unwrap();
+        assert!(path.ends_with("0008-alice-task-1.json"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("0007-alice-task-1.json")).unwrap(),
+            "{}"
+        );
+    }
+}
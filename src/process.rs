@@ -0,0 +1,166 @@
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Only the start of a student's output is kept, a println in an endless loop would fill the memory.
+const MAX_CAPTURED_BYTES: usize = 1024 * 1024;
+
+//output of a command run with run_limited
+pub struct LimitedOutput {
+    // None if the command was killed because of the timeout
+    pub status: Option<ExitStatus>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+//Runs cmd in its own process group with an address space limit (0 = no limit).
+//If it is still running after timeout, the whole group is killed. The group is killed after a
+//normal exit as well, so nothing started by `sh -c` or the JVM survives and keeps the pipes open.
+pub fn run_limited(
+    cmd: &mut Command,
+    timeout: Duration,
+    max_memory_mb: u64,
+) -> io::Result<LimitedOutput> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    if max_memory_mb > 0 {
+        let bytes = max_memory_mb.saturating_mul(1024 * 1024) as libc::rlim_t;
+        // SAFETY: setrlimit is async-signal-safe and only touches the forked child
+        unsafe {
+            cmd.pre_exec(move || {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes,
+                    rlim_max: bytes,
+                };
+                if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    let mut child = cmd.spawn()?;
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+
+    // the child is the leader of its group, so -pid addresses the whole group. The leader is
+    // only reaped after the group is killed, until then its pid (and so the group id) can not
+    // be reused by another student's test run.
+    let pid = child.id() as libc::pid_t;
+    let started = Instant::now();
+    let exited = loop {
+        if has_exited(pid)? {
+            break true;
+        }
+        if started.elapsed() >= timeout {
+            break false;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    // Also after a normal exit, e.g. Runtime.exec("sleep 9999") in student code would hold
+    // stdout open and the reader threads below would never finish.
+    unsafe {
+        libc::kill(-pid, libc::SIGKILL);
+    }
+    let status = child.wait()?;
+    let status = exited.then_some(status);
+
+    Ok(LimitedOutput {
+        timed_out: status.is_none(),
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+// true once the process has exited, without reaping it (WNOWAIT)
+fn has_exited(pid: libc::pid_t) -> io::Result<bool> {
+    loop {
+        // SAFETY: waitid only writes to the zeroed siginfo_t it is given
+        let (result, info) = unsafe {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            let result = libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT | libc::WNOHANG,
+            );
+            (result, info)
+        };
+        if result == 0 {
+            // with WNOHANG si_pid stays 0 while the process is running
+            return Ok(unsafe { info.si_pid() } != 0);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+// Reads a pipe on its own thread so a full stderr pipe can not block the child while we wait on stdout.
+fn capture<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut kept = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let mut buf = [0u8; 8192];
+            while let Ok(n) = pipe.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let room = MAX_CAPTURED_BYTES.saturating_sub(kept.len());
+                kept.extend_from_slice(&buf[..n.min(room)]);
+            }
+        }
+        String::from_utf8_lossy(&kept).into_owned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grandchildren_do_not_keep_the_pipes_open() {
+        let started = Instant::now();
+        let output = run_limited(
+            Command::new("sh").args(["-c", "sleep 30 & echo started"]),
+            Duration::from_secs(20),
+            0,
+        )
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!output.timed_out);
+        assert_eq!(output.stdout.trim(), "started");
+    }
+
+    #[test]
+    fn a_command_over_the_timeout_is_killed() {
+        let output = run_limited(
+            Command::new("sh").args(["-c", "sleep 30"]),
+            Duration::from_millis(200),
+            0,
+        )
+        .unwrap();
+        assert!(output.timed_out);
+        assert!(output.status.is_none());
+    }
+
+    #[test]
+    fn the_exit_status_is_kept() {
+        let output = run_limited(
+            Command::new("sh").args(["-c", "exit 3"]),
+            Duration::from_secs(20),
+            0,
+        )
+        .unwrap();
+        assert!(!output.timed_out);
+        assert_eq!(output.status.and_then(|s| s.code()), Some(3));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::budget::{estimate_tokens, fit_payload, test_output, truncation_lines};
+use crate::config::Config;
+use crate::grading::OUTPUT_CONTRACT;
+use crate::json_parser::{Payload, SourceFile};
+use crate::pseudonym::Pseudonyms;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Compiled into the binary, used when llm.prompt_dir has no override
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("student", include_str!("../prompts/student.txt")),
+    ("teacher", include_str!("../prompts/teacher.txt")),
+];
+
+// Every {{name}} a template can use
+pub const PLACEHOLDERS: &[&str] = &[
+    "task",
+    "readme",
+    "files",
+    "filenames",
+    "student_tests",
+    "test_results",
+    "rubric",
+];
+
+// Rubric file looked up next to the templates, per task first
+const RUBRIC_FILE: &str = "rubric.md";
+
+//a template and where it was found, e.g. "prompts/task-5/student.txt" or "built-in student"
+pub struct Template {
+    pub origin: String,
+    pub text: String,
+}
+
+//Finds the template for `selection` (llm.prompt or --prompt). "student" and "teacher" are looked up
+//in llm.prompt_dir/<task>/ and llm.prompt_dir/ before the built-in ones, anything else is a file.
+pub fn load_template(
+    config: &Config,
+    selection: &str,
+    task: &str,
+) -> Result<Template, Box<dyn std::error::Error>> {
+    let Some((name, builtin)) = BUILTIN_TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == selection)
+    else {
+        return read_template(Path::new(selection));
+    };
+    match find_override(config, task, &format!("{}.txt", name)) {
+        Some(path) => read_template(&path),
+        None => Ok(Template {
+            origin: format!("built-in {}", name),
+            text: builtin.to_string(),
+        }),
+    }
+}
+
+//The prompt for one student: the template from llm.prompt filled with the payload,
+//followed by the JSON answer format. The payload is shortened first if the prompt would be
+//over `budget` estimated tokens.
+pub fn build_prompt(
+    config: &Config,
+    payload: &mut Payload,
+    budget: usize,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let template = load_template(config, &config.llm.prompt, task_name(config, payload))?;
+    fit_to_budget(config, &template, payload, budget)?;
+    render_payload(config, &template, payload)
+}
+
+//Fits the payload into what the template (with the rubric and the answer format) leaves
+//of the budget, see budget::fit_payload.
+pub fn fit_to_budget(
+    config: &Config,
+    template: &Template,
+    payload: &mut Payload,
+    budget: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bare = payload.clone();
+    bare.read_me.clear();
+    bare.test_summary.clear();
+    bare.test_results.clear();
+    bare.student_tests.clear();
+    for file in &mut bare.source_files {
+        file.content.clear();
+    }
+    let overhead = estimate_tokens(&render_payload(config, template, &bare)?);
+    if overhead >= budget {
+        return Err(format!(
+            "the prompt template {} alone is ~{} tokens, over the budget of {}",
+            template.origin, overhead, budget
+        )
+        .into());
+    }
+    // 1% for the markers that say what was left out
+    fit_payload(
+        payload,
+        (budget - overhead).saturating_sub(budget / 100),
+        budget,
+    );
+    Ok(())
+}
+
+pub fn render_payload(
+    config: &Config,
+    template: &Template,
+    payload: &Payload,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let task = task_name(config, payload);
+    let filenames: Vec<&str> = payload
+        .source_files
+        .iter()
+        .map(|f| f.filename.as_str())
+        .collect();
+    let rubric = match find_override(config, task, RUBRIC_FILE) {
+        Some(path) => fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read the rubric {}: {}", path.display(), e))?,
+        None => String::new(),
+    };
+    let mut prompt = render(
+        &template.text,
+        &[
+            ("task", task),
+            ("readme", &payload.read_me),
+            ("files", &format_files(&payload.source_files)),
+            ("filenames", &filenames.join(", ")),
+            ("student_tests", &format_files(&payload.student_tests)),
+            ("test_results", test_output(payload)),
+            ("rubric", or_none(rubric.trim())),
+        ],
+    )
+    .map_err(|e| format!("Prompt template {}: {}", template.origin, e))?;
+    prompt.push_str(OUTPUT_CONTRACT);
+    Ok(prompt)
+}
+
+//Replaces every {{name}} with its value in one pass, so the values (student code) are never
+//scanned for placeholders. Braces that do not form {{name}} are kept as they are, an unknown
+//name is an error since it is almost always a typo.
+pub fn render(template: &str, values: &[(&str, &str)]) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let inner = &rest[start + 2..];
+        let name = inner
+            .find("}}")
+            .map(|end| (inner[..end].trim(), end))
+            .filter(|(name, _)| is_placeholder_name(name));
+        let Some((name, end)) = name else {
+            // a literal brace, e.g. `new int[][] {{1, 2}}`, the next one can still open a placeholder
+            out.push('{');
+            rest = &rest[start + 1..];
+            continue;
+        };
+        let value = values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                format!(
+                    "unknown placeholder {{{{{}}}}}, known are {}",
+                    name,
+                    PLACEHOLDERS.join(", ")
+                )
+            })?;
+        out.push_str(value);
+        rest = &inner[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+//Prints the prompt `imagi generate` would send for one payload, for checking a template.
+pub fn print_prompt(
+    config: &Config,
+    payload_path: &Path,
+    budget: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(payload_path)?;
+    let mut payload: Payload = serde_json::from_str(&content)
+        .map_err(|e| format!("{} is not a payload: {}", payload_path.display(), e))?;
+    // the prompt as the model sees it, the headline keeps the real ID
+    let student = payload.user_id.clone();
+    if let Some(mut pseudonyms) = Pseudonyms::new(config, &student)? {
+        pseudonyms.scrub_payload(&mut payload);
+    }
+    let template = load_template(config, &config.llm.prompt, task_name(config, &payload))?;
+    fit_to_budget(config, &template, &mut payload, budget)?;
+    let prompt = render_payload(config, &template, &payload)?;
+    // on stderr so the prompt itself can be redirected to a file
+    eprintln!(
+        "\x1b[1;34m📝 {} ({}) with template {}, ~{} of {} tokens\x1b[0m",
+        student,
+        payload.task,
+        template.origin,
+        estimate_tokens(&prompt),
+        budget
+    );
+    if let Some(truncation) = &payload.truncation {
+        for line in truncation_lines(&student, truncation) {
+            eprintln!("\x1b[33m{}\x1b[0m", line);
+        }
+    }
+    println!("{}", prompt);
+    Ok(())
+}
+
+// The registry name, so overrides in llm.prompt_dir/<task> also apply when the task was given by alias
+fn task_name<'a>(config: &'a Config, payload: &'a Payload) -> &'a str {
+    config
+        .find_task(&payload.task)
+        .map(|task| task.name.as_str())
+        .unwrap_or(&payload.task)
+}
+
+// llm.prompt_dir/<task>/<file>, then llm.prompt_dir/<file>
+fn find_override(config: &Config, task: &str, file: &str) -> Option<PathBuf> {
+    let dir = config.llm.prompt_dir.as_ref()?;
+    [dir.join(task).join(file), dir.join(file)]
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+fn read_template(path: &Path) -> Result<Template, Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Could not read the prompt template {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    Ok(Template {
+        origin: path.display().to_string(),
+        text,
+    })
+}
+
+// Every file with its name on top, fenced so the model sees where one ends
+fn format_files(files: &[SourceFile]) -> String {
+    let blocks: Vec<String> = files
+        .iter()
+        .map(|f| {
+            format!(
+                "// File: {}\n```java\n{}\n```",
+                f.filename,
+                f.content.trim_end()
+            )
+        })
+        .collect();
+    or_none(&blocks.join("\n\n")).to_string()
+}
+
+fn or_none(value: &str) -> &str {
+    if value.is_empty() { "(none)" } else { value }
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let out = render(
+            "Task {{task}}:\n{{ files }}\n{{task}}",
+            &[("task", "task-1"), ("files", "Calc.java")],
+        )
+        .unwrap();
+        assert_eq!(out, "Task task-1:\nCalc.java\ntask-1");
+    }
+
+    #[test]
+    fn values_are_not_scanned_for_placeholders() {
+        // student code that happens to contain {{readme}} stays as it is
+        let out = render(
+            "{{files}} / {{readme}}",
+            &[
+                ("files", "String s = \"{{readme}}\";"),
+                ("readme", "README"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(out, "String s = \"{{readme}}\"; / README");
+    }
+
+    #[test]
+    fn literal_braces_are_kept() {
+        let template = "int[][] grid = new int[][] {{1, 2}, {3, 4}};\n{{{task}}}\n{{ }} {{";
+        let out = render(template, &[("task", "task-1")]).unwrap();
+        assert_eq!(
+            out,
+            "int[][] grid = new int[][] {{1, 2}, {3, 4}};\n{task-1}\n{{ }} {{"
+        );
+    }
+
+    #[test]
+    fn an_unknown_placeholder_is_an_error() {
+        let err = render("Hello {{studnet}}", &[("task", "task-1")]).unwrap_err();
+        assert!(err.contains("{{studnet}}"), "{}", err);
+        assert!(err.contains("task, readme"), "{}", err);
+    }
+
+    #[test]
+    fn the_builtin_templates_only_use_known_placeholders() {
+        let values: Vec<(&str, &str)> = PLACEHOLDERS.iter().map(|name| (*name, "")).collect();
+        for (name, template) in BUILTIN_TEMPLATES {
+            assert!(render(template, &values).is_ok(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use crate::config::Config;
+use crate::grading::GradeResponse;
+use crate::json_parser::{Payload, SourceFile};
+use crate::roster::read_roster;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+// Name parts shorter than this are not scrubbed on their own, "Li" would hit too much text
+const MIN_NAME_PART: usize = 3;
+
+//The replacements for one student: what the model sees instead of IDs, names and emails, and
+//the way back. Tokens are derived from privacy.key_file, so a student gets the same pseudonym
+//in every run and the cache keeps working.
+pub struct Pseudonyms {
+    key: String,
+    // (term, kind of token), longest first so "Alice Andersson" wins over "Alice"
+    terms: Vec<(String, &'static str)>,
+    scrub_emails: bool,
+    // token -> original, for everything that was replaced in this payload
+    replaced: Vec<(String, String)>,
+}
+
+impl Pseudonyms {
+    //None if privacy.enabled is off. The roster is read for every student, it is small.
+    pub fn new(
+        config: &Config,
+        student: &str,
+    ) -> Result<Option<Pseudonyms>, Box<dyn std::error::Error>> {
+        let privacy = &config.privacy;
+        if !privacy.enabled {
+            return Ok(None);
+        }
+        let mut pseudonyms =
+            Pseudonyms::with_key(load_key(&privacy.key_file)?, privacy.scrub_emails);
+        pseudonyms.add("student", student);
+        if let Some(roster) = &privacy.roster {
+            let entries = read_roster(roster).map_err(|e| {
+                format!("Could not read privacy.roster {}: {}", roster.display(), e)
+            })?;
+            for entry in entries {
+                pseudonyms.add("student", &entry.student);
+                if let Some(name) = entry.name {
+                    pseudonyms.add_name(&name);
+                }
+            }
+        }
+        for term in &privacy.terms {
+            pseudonyms.add_name(term);
+        }
+        Ok(Some(pseudonyms))
+    }
+
+    fn with_key(key: String, scrub_emails: bool) -> Pseudonyms {
+        Pseudonyms {
+            key,
+            terms: Vec::new(),
+            scrub_emails,
+            replaced: Vec::new(),
+        }
+    }
+
+    //The student's ID becomes its pseudonym, the texts and filenames are scrubbed.
+    //restore_payload undoes it exactly.
+    pub fn scrub_payload(&mut self, payload: &mut Payload) {
+        payload.user_id = self.scrub(&payload.user_id);
+        payload.read_me = self.scrub(&payload.read_me);
+        payload.test_results = self.scrub(&payload.test_results);
+        payload.test_summary = self.scrub(&payload.test_summary);
+        for file in payload
+            .source_files
+            .iter_mut()
+            .chain(payload.student_tests.iter_mut())
+        {
+            self.scrub_file(file);
+        }
+    }
+
+    pub fn restore_payload(&self, payload: &mut Payload) {
+        payload.user_id = self.restore(&payload.user_id);
+        payload.read_me = self.restore(&payload.read_me);
+        payload.test_results = self.restore(&payload.test_results);
+        payload.test_summary = self.restore(&payload.test_summary);
+        for file in payload
+            .source_files
+            .iter_mut()
+            .chain(payload.student_tests.iter_mut())
+        {
+            file.filename = self.restore(&file.filename);
+            file.content = self.restore(&file.content);
+        }
+        if let Some(truncation) = payload.truncation.as_mut() {
+            for line in &mut truncation.elided {
+                *line = self.restore(line);
+            }
+        }
+    }
+
+    //Puts the originals back into the model's answer before it is saved or posted
+    pub fn restore_grade(&self, grade: &mut GradeResponse) {
+        grade.feedback = self.restore(&grade.feedback);
+        for issue in &mut grade.issues {
+            issue.file = self.restore(&issue.file);
+            issue.message = self.restore(&issue.message);
+        }
+    }
+
+    pub fn restore(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (token, original) in &self.replaced {
+            if text.contains(token.as_str()) {
+                text = text.replace(token.as_str(), original);
+            }
+        }
+        text
+    }
+
+    //Emails first, so alice@kth.se becomes one token and not a pseudonym with a domain.
+    //Terms are matched as whole words with the case they were given in. A word in a qualified
+    //name (java.util.List, Math.max, Count.java) is code and kept, also if a term equals it.
+    pub fn scrub(&mut self, text: &str) -> String {
+        let text = if self.scrub_emails {
+            self.scrub_emails_in(text)
+        } else {
+            text.to_string()
+        };
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        let mut at_boundary = true;
+        while let Some(c) = rest.chars().next() {
+            if at_boundary && let Some((len, kind)) = self.match_term(rest) {
+                let (original, tail) = rest.split_at(len);
+                let token = self.token(kind, original);
+                self.remember(&token, original);
+                out.push_str(&token);
+                rest = tail;
+                at_boundary = false;
+                continue;
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+            at_boundary = !is_word_char(c) && c != '.';
+        }
+        out
+    }
+
+    //Only the comments and the string and char literals of Java code are scrubbed, the code
+    //itself has to stay exactly as it is or it is not the code that was graded
+    pub fn scrub_java(&mut self, code: &str) -> String {
+        let mut out = String::with_capacity(code.len());
+        let mut rest = code;
+        while let Some((start, end)) = next_literal(rest) {
+            out.push_str(&rest[..start]);
+            out.push_str(&self.scrub(&rest[start..end]));
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn scrub_file(&mut self, file: &mut SourceFile) {
+        file.filename = self.scrub(&file.filename);
+        file.content = if file.filename.ends_with(".java") {
+            self.scrub_java(&file.content)
+        } else {
+            self.scrub(&file.content)
+        };
+    }
+
+    // A known term that starts here, ends at a word boundary and is not the name of a Java type:
+    // (its length in `text`, kind)
+    fn match_term(&self, text: &str) -> Option<(usize, &'static str)> {
+        self.terms.iter().find_map(|(term, kind)| {
+            let rest = text.strip_prefix(term.as_str())?;
+            let ends_word = rest.chars().next().is_none_or(|c| !is_word_char(c));
+            // a Java type: its file (Count.java) or a member of it (Count.main)
+            let qualified = rest.starts_with(".java")
+                || term.starts_with(char::is_uppercase)
+                    && rest
+                        .strip_prefix('.')
+                        .and_then(|after| after.chars().next())
+                        .is_some_and(is_word_char);
+            (ends_word && !qualified).then_some((term.len(), *kind))
+        })
+    }
+
+    fn scrub_emails_in(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(at) = rest.find('@') {
+            let local_start = rest[..at]
+                .rfind(|c: char| !is_email_local_char(c))
+                .map(|i| i + rest[i..].chars().next().map_or(1, char::len_utf8))
+                .unwrap_or(0);
+            let domain_len = rest[at + 1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+                .unwrap_or(rest.len() - at - 1);
+            let domain = rest[at + 1..at + 1 + domain_len].trim_end_matches('.');
+            // `@author` and `@Test` have nothing in front, a domain has a dot
+            if local_start == at || !domain.contains('.') {
+                out.push_str(&rest[..=at]);
+                rest = &rest[at + 1..];
+                continue;
+            }
+            let email = &rest[local_start..at + 1 + domain.len()];
+            let token = format!("{}@example.invalid", self.token("email", email));
+            self.remember(&token, email);
+            out.push_str(&rest[..local_start]);
+            out.push_str(&token);
+            rest = &rest[at + 1 + domain.len()..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn add(&mut self, kind: &'static str, term: &str) {
+        let term = term.trim();
+        if term.is_empty() || self.terms.iter().any(|(t, _)| t == term) {
+            return;
+        }
+        self.terms.push((term.to_string(), kind));
+        self.terms
+            .sort_by_key(|(term, _)| std::cmp::Reverse(term.len()));
+    }
+
+    // The whole name and every part of it that is long enough
+    fn add_name(&mut self, name: &str) {
+        self.add("name", name);
+        for part in name.split_whitespace() {
+            if part.chars().count() >= MIN_NAME_PART {
+                self.add("name", part);
+            }
+        }
+    }
+
+    // The same term always gets the same token
+    fn token(&self, kind: &str, term: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key.as_bytes());
+        hasher.update([0]);
+        hasher.update(term.as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        format!("{}-{}", kind, &hash[..10])
+    }
+
+    fn remember(&mut self, token: &str, original: &str) {
+        if !self.replaced.iter().any(|(t, _)| t == token) {
+            self.replaced
+                .push((token.to_string(), original.to_string()));
+        }
+    }
+}
+
+// Reads privacy.key_file, or creates it with a new random key that only the owner can read
+fn load_key(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    if let Ok(key) = fs::read_to_string(path) {
+        let key = key.trim().to_string();
+        if !key.is_empty() {
+            return Ok(key);
+        }
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut bytes = [0u8; 32];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .map_err(|e| format!("Could not read a random key from /dev/urandom: {}", e))?;
+    let key: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", key))
+        .map_err(|e| {
+            format!(
+                "Could not create the pseudonym key {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+    Ok(key)
+}
+
+// The next comment, string, text block or char literal in Java code: (start, end) in bytes
+fn next_literal(code: &str) -> Option<(usize, usize)> {
+    let start = code.char_indices().map(|(i, _)| i).find(|&i| {
+        let rest = &code[i..];
+        rest.starts_with(['"', '\'']) || rest.starts_with("//") || rest.starts_with("/*")
+    })?;
+    let rest = &code[start..];
+    let len = if rest.starts_with("//") {
+        rest.find('\n').unwrap_or(rest.len())
+    } else if let Some(comment) = rest.strip_prefix("/*") {
+        comment.find("*/").map_or(rest.len(), |i| i + 4)
+    } else if let Some(block) = rest.strip_prefix("\"\"\"") {
+        block.find("\"\"\"").map_or(rest.len(), |i| i + 6)
+    } else {
+        // "..." or '...', a backslash escapes the next char, a literal ends at the line at the latest
+        let quote = rest.as_bytes()[0];
+        let bytes = rest.as_bytes();
+        let mut i = 1;
+        while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        (i + 1).min(rest.len())
+    };
+    Some((start, start + len))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_email_local_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The roster from the review: IDs and name parts that are also common Java identifiers
+    fn roster() -> Pseudonyms {
+        let mut pseudonyms = Pseudonyms::with_key("test-key".to_string(), true);
+        for (id, name) in [
+            ("alice", "Max Set"),
+            ("bob", "Lee Long"),
+            ("list", "Anna Count"),
+        ] {
+            pseudonyms.add("student", id);
+            pseudonyms.add_name(name);
+        }
+        pseudonyms
+    }
+
+    const CODE: &str = r#"import java.util.List;
+import java.util.Set;
+
+public class Stats {
+    // written by Max Set, max@kth.se
+    private final List<Integer> list = new ArrayList<>();
+    private Set<Integer> seen;
+    /* Anna Count helped */
+    public long count(long max) {
+        int bob = Math.max(1, 2);
+        char c = '"';
+        String who = "Lee Long and alice \"list\"";
+        return list.size() + bob + max;
+    }
+}
+"#;
+
+    #[test]
+    fn java_code_survives_scrubbing() {
+        let mut pseudonyms = roster();
+        let scrubbed = pseudonyms.scrub_java(CODE);
+        for code in [
+            "import java.util.List;",
+            "import java.util.Set;",
+            "private final List<Integer> list = new ArrayList<>();",
+            "private Set<Integer> seen;",
+            "public long count(long max) {",
+            "int bob = Math.max(1, 2);",
+            "char c = '\"';",
+            "return list.size() + bob + max;",
+        ] {
+            assert!(
+                scrubbed.contains(code),
+                "{} was changed:\n{}",
+                code,
+                scrubbed
+            );
+        }
+        for pii in ["Max Set", "max@kth.se", "Anna Count", "Lee Long", "alice"] {
+            assert!(
+                !scrubbed.contains(pii),
+                "{} was not scrubbed:\n{}",
+                pii,
+                scrubbed
+            );
+        }
+        assert!(
+            scrubbed.contains("\\\"student-"),
+            "the ID in the string:\n{}",
+            scrubbed
+        );
+        assert_eq!(pseudonyms.restore(&scrubbed), CODE);
+    }
+
+    #[test]
+    fn text_is_matched_case_sensitively_and_not_after_a_dot() {
+        let mut pseudonyms = roster();
+        let text = "alice uses java.util.List, List and Math.max, Lee Long wrote it";
+        let scrubbed = pseudonyms.scrub(text);
+        assert!(scrubbed.starts_with("student-"));
+        assert!(scrubbed.contains("java.util.List, List and Math.max"));
+        assert!(!scrubbed.contains("Lee Long"));
+        assert_eq!(pseudonyms.restore(&scrubbed), text);
+    }
+
+    #[test]
+    fn ids_that_are_java_words_are_scrubbed_outside_code() {
+        let mut pseudonyms = Pseudonyms::with_key("test-key".to_string(), true);
+        pseudonyms.add("student", "long");
+        pseudonyms.add_name("This Person");
+        let code = "/** @author long, This Person */\npublic class Sum {\n    long total = 0; // long\n    String by = \"long\";\n}\n";
+        let scrubbed = pseudonyms.scrub_java(code);
+        assert!(scrubbed.starts_with("/** @author student-"), "{}", scrubbed);
+        assert!(scrubbed.contains("    long total = 0; // student-"));
+        assert!(scrubbed.contains("String by = \"student-"));
+        assert!(!scrubbed.contains("This"));
+        assert_eq!(scrubbed.matches("long").count(), 1, "{}", scrubbed);
+        assert_eq!(pseudonyms.restore(&scrubbed), code);
+    }
+
+    #[test]
+    fn java_file_names_match_their_classes() {
+        let mut pseudonyms = roster();
+        let mut file = SourceFile {
+            filename: "alice/Count.java".to_string(),
+            content: "// Anna Count\npublic class Count {}\n".to_string(),
+        };
+        pseudonyms.scrub_file(&mut file);
+        assert!(file.filename.starts_with("student-"), "{}", file.filename);
+        assert!(file.filename.ends_with("/Count.java"));
+        assert!(file.content.contains("public class Count {}"));
+        assert!(!file.content.contains("Anna"));
+        // stack traces name the class and its file the same way
+        let output = pseudonyms.scrub("at Count.count(Count.java:7), by Count.");
+        assert!(
+            output.starts_with("at Count.count(Count.java:7), by name-"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn tokens_are_stable() {
+        let a = roster().scrub("Lee Long");
+        let b = roster().scrub("Lee Long");
+        assert_eq!(a, b);
+        assert_ne!(
+            a,
+            Pseudonyms::with_key("other".to_string(), true).token("name", "Lee Long")
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use crate::config::Config;
+use crate::editor::edit_feedback;
+use crate::github_api::{IssueOutcome, send_issue};
+use crate::grading::{GradeResponse, GradeStatus};
+use crate::json_parser::{Payload, Truncation};
+use crate::test_report::{TestOutcome, TestReport};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+//Which issues `generate --non-interactive` may post without a TA looking at them first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostPolicy {
+    // nothing is posted, everything goes to the review queue
+    Never,
+    PassOnly,
+    All,
+    // the AI status agrees with the unit tests: PASS only if all tests passed, otherwise FAIL or KOMPLETTERING
+    StatusMatchesTests,
+}
+
+impl PostPolicy {
+    //Ok if the issue can be posted, otherwise the reason it is queued for review
+    pub fn check(&self, grade: &GradeResponse, report: Option<&TestReport>) -> Result<(), String> {
+        match self {
+            PostPolicy::Never => Err("post policy is never".to_string()),
+            PostPolicy::All => Ok(()),
+            PostPolicy::PassOnly if grade.status == GradeStatus::Pass => Ok(()),
+            PostPolicy::PassOnly => Err(format!("status is {}, not PASS", grade.status)),
+            PostPolicy::StatusMatchesTests => {
+                // Payloads from older versions have no parsed report to compare with
+                let report = report.ok_or("the payload has no test report")?;
+                let tests_passed = report.outcome == TestOutcome::Passed;
+                if tests_passed == (grade.status == GradeStatus::Pass) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "status {} does not match the tests ({})",
+                        grade.status,
+                        report.status_line()
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for PostPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(PostPolicy::Never),
+            "pass-only" => Ok(PostPolicy::PassOnly),
+            "all" => Ok(PostPolicy::All),
+            "status-matches-tests" => Ok(PostPolicy::StatusMatchesTests),
+            _ => Err(format!(
+                "unknown post policy '{}' (expected never, pass-only, all or status-matches-tests)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PostPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PostPolicy::Never => "never",
+            PostPolicy::PassOnly => "pass-only",
+            PostPolicy::All => "all",
+            PostPolicy::StatusMatchesTests => "status-matches-tests",
+        })
+    }
+}
+
+//where a review item is, `imagi review` shows the pending and approved ones
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewState {
+    Pending,
+    // approved in the TUI, posted together later
+    Approved,
+    Posted,
+    Rejected,
+}
+
+impl ReviewState {
+    //true until the item is posted or rejected
+    pub fn is_open(&self) -> bool {
+        matches!(self, ReviewState::Pending | ReviewState::Approved)
+    }
+}
+
+//Graded feedback for one student. It is written before anyone looks at it and updated after every
+//decision, so quitting generate or review loses nothing.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReviewItem {
+    pub student_id: String,
+    pub task: String,
+    pub state: ReviewState,
+    // the status the issue is posted with, the TA can change it
+    pub status: GradeStatus,
+    // what the grader answered, kept when the TA changes the status
+    pub ai_status: GradeStatus,
+    pub ai_feedback: String,
+    pub confidence: f64,
+    // the issue body as edited by the TA, replaces the generated one
+    #[serde(default)]
+    pub body: Option<String>,
+    pub test_summary: String,
+    // commit the feedback is about
+    #[serde(default)]
+    pub commit: Option<String>,
+    // why it was not posted automatically
+    #[serde(default)]
+    pub reason: Option<String>,
+    // what was left out of the payload to fit the prompt, the model did not see it
+    #[serde(default)]
+    pub truncation: Option<Truncation>,
+    // RFC3339, when the item was last changed
+    pub updated_at: String,
+}
+
+impl ReviewItem {
+    pub fn new(
+        payload: &Payload,
+        grade: &GradeResponse,
+        truncation: Option<Truncation>,
+    ) -> ReviewItem {
+        ReviewItem {
+            student_id: payload.user_id.clone(),
+            task: payload.task.clone(),
+            state: ReviewState::Pending,
+            status: grade.status,
+            ai_status: grade.status,
+            ai_feedback: grade.feedback.clone(),
+            confidence: grade.confidence,
+            body: None,
+            test_summary: payload.test_summary.clone(),
+            commit: payload.commit.clone(),
+            reason: None,
+            truncation,
+            updated_at: Local::now().to_rfc3339(),
+        }
+    }
+
+    //the issue body, unless edited the AI part is marked as optional suggestions
+    pub fn issue_body(&self) -> String {
+        match &self.body {
+            Some(body) => body.clone(),
+            None => format!(
+                "🤖 **AI Suggestions** (optional improvements, not requirements):\n\n{}\n\nNote: These suggestions are meant to help you learn and improve - they are not mandatory requirements that must be completed.",
+                self.ai_feedback
+            ),
+        }
+    }
+
+    //Writes the item to the review queue, replacing an older item of the same student.
+    pub fn save(&mut self, output_dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let dir = review_dir(output_dir);
+        fs::create_dir_all(&dir)?;
+        self.updated_at = Local::now().to_rfc3339();
+        let path = review_item_path(output_dir, &self.student_id);
+        // write and rename, a crash halfway must not leave half an item behind
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    //posts the issue, the item stays pending if GitHub does not accept it or with --dry-run.
+    //None if the request could not be sent at all.
+    pub async fn post(
+        &mut self,
+        config: &Config,
+        output_dir: &Path,
+    ) -> Result<Option<IssueOutcome>, Box<dyn std::error::Error>> {
+        let outcome = send_issue(
+            config,
+            self.task.clone(),
+            self.student_id.clone(),
+            self.status,
+            self.issue_body(),
+        )
+        .await;
+        match &outcome {
+            Ok(IssueOutcome::Created) => {
+                self.state = ReviewState::Posted;
+                self.reason = None;
+            }
+            Ok(IssueOutcome::Rejected) => {
+                self.reason = Some("GitHub rejected the issue".to_string());
+            }
+            Ok(IssueOutcome::DryRun(path)) => {
+                self.reason = Some(format!("dry run, the issue is in {}", path.display()));
+            }
+            Err(e) => {
+                self.reason = Some(format!("could not post the issue: {}", e));
+            }
+        }
+        self.save(output_dir)?;
+        Ok(outcome.ok())
+    }
+}
+
+//the queue lives next to the feedback files, one JSON file per student
+pub fn review_dir(output_dir: &Path) -> PathBuf {
+    output_dir.join("review")
+}
+
+pub fn review_item_path(output_dir: &Path, student: &str) -> PathBuf {
+    review_dir(output_dir).join(format!("{}.json", student))
+}
+
+//every item in the queue, sorted by student
+pub fn load_review_items(output_dir: &Path) -> Result<Vec<ReviewItem>, Box<dyn std::error::Error>> {
+    let dir = review_dir(output_dir);
+    let mut items = Vec::new();
+    if !dir.is_dir() {
+        return Ok(items);
+    }
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            let item: ReviewItem = serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| format!("{} is not a review item: {}", path.display(), e))?;
+            items.push(item);
+        }
+    }
+    items.sort_by(|a, b| a.student_id.cmp(&b.student_id));
+    Ok(items)
+}
+
+//what the TA decided for one item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Posted,
+    // skipped, or posting failed
+    Pending,
+    Rejected,
+    // stop reviewing, the item stays pending
+    Quit,
+}
+
+pub fn print_review_item(item: &ReviewItem) {
+    println!("\n{}", "=".repeat(80));
+    println!(
+        "\x1b[1;36m📝 FEEDBACK READY FOR: {}\x1b[0m",
+        item.student_id.to_uppercase()
+    );
+    println!("{}", "=".repeat(80));
+
+    println!("\x1b[1;33m📋 Task:\x1b[0m {}", item.task);
+    if let Some(commit) = &item.commit {
+        println!("\x1b[1;33m🔖 Commit:\x1b[0m {}", commit);
+    }
+    println!(
+        "\x1b[1;32m📊 Status:\x1b[0m {} \x1b[90m(confidence {:.0}%)\x1b[0m",
+        item.status.colored(0),
+        item.confidence * 100.0
+    );
+    if item.status != item.ai_status {
+        println!("   \x1b[90m(the AI said {})\x1b[0m", item.ai_status);
+    }
+    if let Some(outcome) = item.test_summary.lines().next() {
+        println!(
+            "\x1b[1;32m🧪 Tests:\x1b[0m {}",
+            outcome.trim_start_matches("Outcome: ")
+        );
+    }
+    if let Some(reason) = &item.reason {
+        println!("\x1b[1;33m🗂️  Not posted:\x1b[0m {}", reason);
+    }
+    if let Some(truncation) = &item.truncation {
+        println!(
+            "\x1b[1;33m✂️  Shortened:\x1b[0m the model saw ~{} of ~{} payload tokens",
+            truncation.kept_tokens, truncation.original_tokens
+        );
+        for elided in &truncation.elided {
+            println!("   \x1b[90m- {}\x1b[0m", elided);
+        }
+    }
+
+    let (title, text) = match &item.body {
+        Some(body) => ("✏️  Edited Feedback:", body),
+        None => ("💬 Generated Feedback:", &item.ai_feedback),
+    };
+    println!("\n\x1b[1;35m{}\x1b[0m", title);
+    println!("{}", "-".repeat(50));
+    // Format feedback with proper line breaks and indentation
+    for line in text.lines() {
+        println!("  {}", line);
+    }
+    println!("{}", "-".repeat(50));
+}
+
+//Asks the TA what to do with a pending item until it is posted, rejected or skipped.
+//The item is saved after every change.
+pub async fn review_item(
+    config: &Config,
+    item: &mut ReviewItem,
+    output_dir: &Path,
+) -> Result<Decision, Box<dyn std::error::Error>> {
+    loop {
+        println!("\n\x1b[1;34m🤔 What would you like to do with this feedback?\x1b[0m");
+        println!(
+            "   \x1b[32m[a]\x1b[0m Approve and create issue   \x1b[36m[e]\x1b[0m Edit in $EDITOR and create issue   \x1b[33m[s]\x1b[0m Change status"
+        );
+        println!(
+            "   \x1b[90m[k]\x1b[0m Skip for now   \x1b[31m[r]\x1b[0m Reject, never post   \x1b[90m[q]\x1b[0m Quit reviewing"
+        );
+        let choice = prompt("➤ Your choice: ")?;
+        match choice.trim() {
+            "a" => {
+                if let Some(IssueOutcome::Created) = item.post(config, output_dir).await? {
+                    return Ok(Decision::Posted);
+                }
+                println!("   \x1b[90m🗂️  Kept in the review queue\x1b[0m");
+                return Ok(Decision::Pending);
+            }
+            "e" => match edit_feedback(item)? {
+                Some((status, body)) => {
+                    item.status = status;
+                    item.body = Some(body);
+                    if let Some(IssueOutcome::Created) = item.post(config, output_dir).await? {
+                        return Ok(Decision::Posted);
+                    }
+                    println!("   \x1b[90m🗂️  Edits saved, kept in the review queue\x1b[0m");
+                    return Ok(Decision::Pending);
+                }
+                None => {
+                    println!("   \x1b[90m⏭️  Editor aborted, skipped, still pending\x1b[0m");
+                    return Ok(Decision::Pending);
+                }
+            },
+            "s" => {
+                let answer = prompt("➤ New status (PASS, FAIL or KOMPLETTERING): ")?;
+                match answer.parse::<GradeStatus>() {
+                    Ok(status) if status.is_graded() => {
+                        item.status = status;
+                        item.save(output_dir)?;
+                        println!("   Status is now {}", status.colored(0));
+                    }
+                    Ok(status) => println!("\x1b[1;31m❌ {} can not be posted\x1b[0m", status),
+                    Err(e) => println!("\x1b[1;31m❌ {}\x1b[0m", e),
+                }
+            }
+            "k" => {
+                println!("   \x1b[90m⏭️  Skipped, still pending\x1b[0m");
+                return Ok(Decision::Pending);
+            }
+            "r" => {
+                item.state = ReviewState::Rejected;
+                item.save(output_dir)?;
+                println!("   \x1b[90m🚫 Rejected, no GitHub issue will be created.\x1b[0m");
+                return Ok(Decision::Rejected);
+            }
+            "q" => return Ok(Decision::Quit),
+            _ => println!(
+                "\x1b[1;31m❌ Invalid input!\x1b[0m Please enter one of a, e, s, k, r or q"
+            ),
+        }
+    }
+}
+
+//`imagi review`: walks the pending items of an output directory. Items that are skipped or
+//not reviewed because of quitting stay pending for the next run.
+pub async fn review_queue(
+    config: &Config,
+    output_dir: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut items: Vec<ReviewItem> = load_review_items(&output_dir)?
+        .into_iter()
+        .filter(|item| item.state.is_open())
+        .collect();
+    if items.is_empty() {
+        println!(
+            "✅ Nothing to review in {}",
+            review_dir(&output_dir).display()
+        );
+        return Ok(());
+    }
+    println!("🗂️  {} item(s) waiting for review", items.len());
+
+    let (mut posted, mut rejected) = (0, 0);
+    for item in &mut items {
+        print_review_item(item);
+        match review_item(config, item, &output_dir).await? {
+            Decision::Posted => posted += 1,
+            Decision::Rejected => rejected += 1,
+            Decision::Pending => {}
+            Decision::Quit => break,
+        }
+        println!("{}\n", "=".repeat(80));
+    }
+    let pending = items.len() - posted - rejected;
+    println!(
+        "📬 Posted {}, rejected {}, {} still pending",
+        posted, rejected, pending
+    );
+    Ok(())
+}
+
+// Reads one line of the TA's answer. Without a terminal the prompts would never be answered,
+// so a closed stdin stops instead of asking again forever.
+fn read_answer(buf: &mut String) -> Result<(), Box<dyn std::error::Error>> {
+    if io::stdin().read_line(buf)? == 0 {
+        return Err("stdin was closed, use --non-interactive to grade without prompts".into());
+    }
+    Ok(())
+}
+
+fn prompt(question: &str) -> Result<String, Box<dyn std::error::Error>> {
+    print!("\x1b[1;37m{}\x1b[0m", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    read_answer(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
@@ -0,0 +1,124 @@
+use chrono::{DateTime, FixedOffset};
+use std::fs::File;
+use std::io::BufRead;
+use std::path::Path;
+
+//one line of students.txt
+pub struct RosterEntry {
+    pub student: String,
+    // full name, only used to scrub it from payloads (privacy.roster)
+    pub name: Option<String>,
+    // per-student extension, overrides --deadline
+    pub deadline: Option<DateTime<FixedOffset>>,
+}
+
+//Reads students.txt. Each line is a kth_ID, optionally followed by the student's name and an
+//RFC3339 deadline extension:
+//
+//    alice
+//    bob 2025-10-24T23:59:00+02:00
+//    carol Carol Svensson 2025-10-24T23:59:00+02:00
+//    # comments and empty lines are skipped
+pub fn read_roster(path: &Path) -> Result<Vec<RosterEntry>, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let buf = std::io::BufReader::new(file);
+    let mut entries = Vec::new();
+    for (number, line) in buf.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue; // Skip empty lines and comments
+        }
+        let mut parts = line.split_whitespace();
+        let student = parts.next().unwrap_or_default().to_string();
+        // names do not start with a digit, deadlines do
+        let (name, rest): (Vec<&str>, Vec<&str>) =
+            parts.partition(|part| !part.starts_with(|c: char| c.is_ascii_digit()));
+        let deadline = match rest.as_slice() {
+            [] => None,
+            [raw] => Some(
+                parse_deadline(raw)
+                    .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?,
+            ),
+            _ => {
+                return Err(
+                    format!("{}:{}: more than one deadline", path.display(), number + 1).into(),
+                );
+            }
+        };
+        let name = (!name.is_empty()).then(|| name.join(" "));
+        entries.push(RosterEntry {
+            student,
+            name,
+            deadline,
+        });
+    }
+    Ok(entries)
+}
+
+//parses an RFC3339 timestamp such as 2025-10-17T23:59:00+02:00, also used for --deadline
+pub fn parse_deadline(raw: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(raw).map_err(|e| {
+        format!(
+            "invalid deadline '{}' (expected RFC3339, e.g. 2025-10-17T23:59:00+02:00): {}",
+            raw, e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn roster(text: &str) -> Result<Vec<RosterEntry>, String> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        read_roster(file.path()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn names_and_deadlines_are_optional() {
+        let entries = roster(
+            "# task 5\n\nalice\nbob 2025-10-24T23:59:00+02:00\n  carol Carol Anne Svensson 2025-10-24T22:00:00Z  \ndave Dave\n",
+        )
+        .unwrap();
+        let students: Vec<&str> = entries.iter().map(|e| e.student.as_str()).collect();
+        assert_eq!(students, ["alice", "bob", "carol", "dave"]);
+        assert!(entries[0].name.is_none() && entries[0].deadline.is_none());
+        assert!(entries[1].name.is_none());
+        assert_eq!(
+            entries[1].deadline,
+            Some(parse_deadline("2025-10-24T21:59:00Z").unwrap())
+        );
+        assert_eq!(entries[2].name.as_deref(), Some("Carol Anne Svensson"));
+        assert_eq!(
+            entries[2].deadline.unwrap().to_rfc3339(),
+            "2025-10-24T22:00:00+00:00"
+        );
+        assert_eq!(entries[3].name.as_deref(), Some("Dave"));
+        assert!(entries[3].deadline.is_none());
+    }
+
+    #[test]
+    fn bad_deadlines_name_the_line() {
+        let err = roster("alice\nbob 2025-10-24\n").err().unwrap();
+        assert!(err.contains(":2: invalid deadline '2025-10-24'"), "{}", err);
+        let err = roster("# header\nbob 2025-10-24T23:59:00Z 2025-10-31T23:59:00Z\n")
+            .err()
+            .unwrap();
+        assert!(err.ends_with(":2: more than one deadline"), "{}", err);
+    }
+
+    #[test]
+    fn deadlines_need_an_offset() {
+        let deadline = parse_deadline("2025-10-17T23:59:00+02:00").unwrap();
+        assert_eq!(deadline.offset().local_minus_utc(), 2 * 3600);
+        assert!(parse_deadline("2025-10-17T23:59:00").is_err());
+        assert!(
+            parse_deadline("tomorrow")
+                .unwrap_err()
+                .contains("expected RFC3339")
+        );
+    }
+}
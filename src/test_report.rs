@@ -0,0 +1,646 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+// Stack frames from these packages are JUnit/JVM plumbing and only add noise.
+const FRAMEWORK_FRAMES: &[&str] = &[
+    "org.junit.",
+    "junit.framework.",
+    "org.hamcrest.",
+    "sun.reflect.",
+    "java.lang.reflect.",
+    "jdk.internal.",
+    "java.base/",
+];
+
+// How many frames of a stack trace are kept after the framework frames are removed.
+const MAX_TRACE_LINES: usize = 5;
+
+// What the JVM prints when the heap (-Xmx) or the address space limit is exhausted.
+const OUT_OF_MEMORY_MARKERS: &[&str] = &[
+    "java.lang.OutOfMemoryError",
+    "Could not reserve enough space",
+    "Could not allocate",
+    "insufficient memory for the Java Runtime",
+];
+
+//overall result of compiling and running the tests for one student
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TestOutcome {
+    Passed,
+    TestsFailed,
+    CompilationFailed,
+    NoTestClasses,
+    ExecutionFailed,
+    // javac or the tests did not finish within the configured timeout
+    TimedOut,
+    OutOfMemory,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CaseStatus {
+    Pass,
+    // an assertion did not hold
+    Fail,
+    // the test threw something other than an assertion error
+    Error,
+    // never started, the test class could not be initialized (JUnit's initializationError)
+    NotRun,
+}
+
+//one test method
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestCase {
+    pub class: String,
+    pub name: String,
+    pub status: CaseStatus,
+    pub message: Option<String>,
+    // student code frames of the stack trace, framework frames removed
+    pub stack_trace: Vec<String>,
+}
+
+//one javac error or warning
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompileDiagnostic {
+    pub file: String,
+    pub line: Option<u32>,
+    // "error" or "warning"
+    pub kind: String,
+    pub message: String,
+    // the source line and caret javac prints below the message
+    pub context: Vec<String>,
+}
+
+//structured version of the test output, stored next to the raw text in the payload
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestReport {
+    pub outcome: TestOutcome,
+    pub tests_run: usize,
+    pub failures: usize,
+    pub time_secs: Option<f64>,
+    pub tests: Vec<TestCase>,
+    pub diagnostics: Vec<CompileDiagnostic>,
+    // explanation for outcomes that have no tests or diagnostics, e.g. why execution failed
+    pub message: Option<String>,
+}
+
+impl TestReport {
+    fn empty(outcome: TestOutcome, message: Option<String>) -> TestReport {
+        TestReport {
+            outcome,
+            tests_run: 0,
+            failures: 0,
+            time_secs: None,
+            tests: Vec::new(),
+            diagnostics: Vec::new(),
+            message,
+        }
+    }
+
+    pub fn compilation_failed(javac_output: &str) -> TestReport {
+        let mut report = TestReport::empty(TestOutcome::CompilationFailed, None);
+        report.diagnostics = parse_javac_output(javac_output);
+        report
+    }
+
+    pub fn no_test_classes() -> TestReport {
+        TestReport::empty(
+            TestOutcome::NoTestClasses,
+            Some("No *Test.java or *Tests.java files were found after compilation.".to_string()),
+        )
+    }
+
+    //stage is "compilation" or "test run"
+    pub fn timed_out(stage: &str, secs: u64) -> TestReport {
+        TestReport::empty(
+            TestOutcome::TimedOut,
+            Some(format!(
+                "The {} did not finish within {}s and was killed.",
+                stage, secs
+            )),
+        )
+    }
+
+    //marks the report as out of memory, keeping the tests that were parsed so far
+    pub fn out_of_memory(mut self) -> TestReport {
+        self.outcome = TestOutcome::OutOfMemory;
+        self.message =
+            Some("The JVM ran out of memory (java.max_heap / java.max_memory_mb).".to_string());
+        self
+    }
+
+    pub fn error(message: String) -> TestReport {
+        TestReport::empty(TestOutcome::Error, Some(message))
+    }
+
+    pub fn passed(&self) -> usize {
+        self.tests_run.saturating_sub(self.failures)
+    }
+
+    pub fn failed_tests(&self) -> impl Iterator<Item = &TestCase> {
+        self.tests
+            .iter()
+            .filter(|t| matches!(t.status, CaseStatus::Fail | CaseStatus::Error))
+    }
+
+    //the line printed after "Processing student ..."
+    pub fn status_line(&self) -> String {
+        match self.outcome {
+            TestOutcome::Passed => {
+                format!("✅ TESTS PASSED ({}/{})", self.passed(), self.tests_run)
+            }
+            TestOutcome::TestsFailed => format!(
+                "❌ TESTS FAILED ({}/{} passed)",
+                self.passed(),
+                self.tests_run
+            ),
+            TestOutcome::CompilationFailed => {
+                let errors = self
+                    .diagnostics
+                    .iter()
+                    .filter(|d| d.kind == "error")
+                    .count();
+                format!("❌ COMPILATION FAILED ({} errors)", errors)
+            }
+            TestOutcome::NoTestClasses => "⚠️ NO TEST CLASSES FOUND".to_string(),
+            TestOutcome::ExecutionFailed => "❌ TEST EXECUTION FAILED".to_string(),
+            TestOutcome::TimedOut => "⏱️ TIMED OUT".to_string(),
+            TestOutcome::OutOfMemory => "💥 OUT OF MEMORY".to_string(),
+            TestOutcome::Error => format!(
+                "❌ ERROR: {}",
+                self.message.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+
+    //Plain-text rendering of the report, used in the terminal and in the AI prompt.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Outcome: {}", self.status_line());
+        if self.tests_run > 0 {
+            let _ = write!(
+                out,
+                "Tests run: {}, passed: {}, failed: {}",
+                self.tests_run,
+                self.passed(),
+                self.failures
+            );
+            if let Some(time) = self.time_secs {
+                let _ = write!(out, ", time: {:.3}s", time);
+            }
+            out.push('\n');
+        }
+        if let Some(message) = &self.message {
+            let _ = writeln!(out, "{}", message);
+        }
+        for diagnostic in &self.diagnostics {
+            let _ = writeln!(
+                out,
+                "{}:{}: {}: {}",
+                diagnostic.file,
+                diagnostic.line.map(|l| l.to_string()).unwrap_or_default(),
+                diagnostic.kind,
+                diagnostic.message
+            );
+            for line in &diagnostic.context {
+                let _ = writeln!(out, "    {}", line);
+            }
+        }
+        for test in &self.tests {
+            let status = match test.status {
+                CaseStatus::Pass => "PASS",
+                CaseStatus::Fail => "FAIL",
+                CaseStatus::Error => "ERROR",
+                CaseStatus::NotRun => "NOT RUN",
+            };
+            let _ = writeln!(out, "[{}] {}.{}", status, test.class, test.name);
+            if let Some(message) = &test.message {
+                let _ = writeln!(out, "    {}", message);
+            }
+            for frame in &test.stack_trace {
+                let _ = writeln!(out, "        at {}", frame);
+            }
+        }
+        out
+    }
+}
+
+pub fn is_out_of_memory(output: &str) -> bool {
+    OUT_OF_MEMORY_MARKERS.iter().any(|m| output.contains(m))
+}
+
+//Parses JUnitCore output. `known_tests` are the (class, method) pairs found in the test sources,
+//used to list the tests that passed since JUnitCore only prints the failing ones. The tests of a
+//class that failed with an initializationError never ran and are listed as not run.
+pub fn parse_junit_output(output: &str, known_tests: &[(String, String)]) -> TestReport {
+    let mut report = TestReport::empty(TestOutcome::ExecutionFailed, None);
+    let mut saw_summary = false;
+    let mut lines = output.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        if let Some(time) = trimmed.strip_prefix("Time:") {
+            report.time_secs = parse_junit_time(time.trim());
+        } else if let Some(count) = trimmed
+            .strip_prefix("OK (")
+            .and_then(|rest| rest.split_whitespace().next())
+        {
+            report.tests_run = count.parse().unwrap_or(0);
+            report.failures = 0;
+            saw_summary = true;
+        } else if let Some(rest) = trimmed.strip_prefix("Tests run:") {
+            // "Tests run: 5,  Failures: 2"
+            let mut numbers = rest
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse::<usize>().unwrap_or(0));
+            report.tests_run = numbers.next().unwrap_or(0);
+            report.failures = numbers.next().unwrap_or(0);
+            saw_summary = true;
+        } else if let Some((name, class)) = parse_failure_header(trimmed) {
+            // "1) testAdd(CalculatorTest)" followed by the exception and its stack trace
+            let mut message_lines = Vec::new();
+            let mut stack_trace = Vec::new();
+            while let Some(next) = lines.peek() {
+                let next_trimmed = next.trim();
+                if parse_failure_header(next_trimmed).is_some()
+                    || next_trimmed == "FAILURES!!!"
+                    || next_trimmed.starts_with("Tests run:")
+                {
+                    break;
+                }
+                let next = lines.next().unwrap_or_default().trim();
+                if let Some(frame) = next.strip_prefix("at ") {
+                    if stack_trace.len() < MAX_TRACE_LINES
+                        && !FRAMEWORK_FRAMES.iter().any(|p| frame.starts_with(p))
+                    {
+                        stack_trace.push(frame.to_string());
+                    }
+                } else if stack_trace.is_empty() && !next.is_empty() && !next.starts_with("...") {
+                    message_lines.push(next.to_string());
+                }
+            }
+            let message = message_lines.join("\n");
+            let status = if is_assertion(&message) {
+                CaseStatus::Fail
+            } else {
+                CaseStatus::Error
+            };
+            report.tests.push(TestCase {
+                class,
+                name,
+                status,
+                message: (!message.is_empty()).then_some(message),
+                stack_trace,
+            });
+        }
+    }
+
+    if !saw_summary {
+        report.message = Some("JUnit did not report any results.".to_string());
+        return report;
+    }
+
+    for (class, name) in known_tests {
+        if !report
+            .tests
+            .iter()
+            .any(|t| &t.class == class && &t.name == name)
+        {
+            let not_initialized = report
+                .tests
+                .iter()
+                .any(|t| &t.class == class && t.name == "initializationError");
+            report.tests.push(TestCase {
+                class: class.clone(),
+                name: name.clone(),
+                status: if not_initialized {
+                    CaseStatus::NotRun
+                } else {
+                    CaseStatus::Pass
+                },
+                message: None,
+                stack_trace: Vec::new(),
+            });
+        }
+    }
+    report.failures = report.failures.max(report.failed_tests().count());
+    report.outcome = if report.failures == 0 {
+        TestOutcome::Passed
+    } else {
+        TestOutcome::TestsFailed
+    };
+    report
+}
+
+// "1) testAdd(CalculatorTest)" -> ("testAdd", "CalculatorTest")
+fn parse_failure_header(line: &str) -> Option<(String, String)> {
+    let (number, rest) = line.split_once(") ")?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (name, class) = rest.strip_suffix(')')?.split_once('(')?;
+    Some((name.to_string(), class.to_string()))
+}
+
+fn is_assertion(message: &str) -> bool {
+    let exception = message.split(':').next().unwrap_or_default();
+    exception.ends_with("AssertionError")
+        || exception.ends_with("AssertionFailedError")
+        || exception.ends_with("ComparisonFailure")
+}
+
+// JUnit formats the time with the default locale, so "0.012", "0,012" and "1,234.5" all occur.
+fn parse_junit_time(raw: &str) -> Option<f64> {
+    let normalized = if raw.contains('.') && raw.contains(',') {
+        raw.replace(',', "")
+    } else {
+        raw.replace(',', ".")
+    };
+    normalized.parse().ok()
+}
+
+//Parses javac output ("Foo.java:12: error: cannot find symbol" plus context lines).
+pub fn parse_javac_output(output: &str) -> Vec<CompileDiagnostic> {
+    let mut diagnostics: Vec<CompileDiagnostic> = Vec::new();
+    for line in output.lines() {
+        if let Some(diagnostic) = parse_javac_header(line) {
+            diagnostics.push(diagnostic);
+        } else if let Some(last) = diagnostics.last_mut() {
+            let trimmed = line.trim_end();
+            // the "3 errors" footer is not part of the last diagnostic
+            let is_footer = trimmed
+                .split_whitespace()
+                .next()
+                .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+                && (trimmed.ends_with("error")
+                    || trimmed.ends_with("errors")
+                    || trimmed.ends_with("warning")
+                    || trimmed.ends_with("warnings"));
+            if !trimmed.is_empty() && !is_footer {
+                last.context.push(trimmed.to_string());
+            }
+        }
+    }
+    diagnostics
+}
+
+fn parse_javac_header(line: &str) -> Option<CompileDiagnostic> {
+    let (file, rest) = line.split_once(".java:")?;
+    let (line_number, rest) = rest.split_once(": ")?;
+    let (kind, message) = rest.split_once(": ")?;
+    if kind != "error" && kind != "warning" {
+        return None;
+    }
+    Some(CompileDiagnostic {
+        file: format!("{}.java", file),
+        line: line_number.parse().ok(),
+        kind: kind.to_string(),
+        message: message.to_string(),
+        context: Vec::new(),
+    })
+}
+
+//Finds the @Test methods in a JUnit 4 test class source.
+pub fn find_test_methods(source: &str) -> Vec<String> {
+    let mut methods = Vec::new();
+    let mut pending = false;
+    for line in source.lines() {
+        let line = line.trim();
+        if line.starts_with("@Test") {
+            pending = true;
+            // "@Test public void foo() {" on a single line
+            if let Some(name) = method_name(line) {
+                methods.push(name);
+                pending = false;
+            }
+        } else if pending && let Some(name) = method_name(line) {
+            methods.push(name);
+            pending = false;
+        }
+    }
+    methods
+}
+
+fn method_name(line: &str) -> Option<String> {
+    let before_paren = line.split_once('(')?.0;
+    if !before_paren.contains("void ") {
+        return None;
+    }
+    before_paren
+        .split_whitespace()
+        .last()
+        .map(|name| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known(tests: &[(&str, &str)]) -> Vec<(String, String)> {
+        tests
+            .iter()
+            .map(|(class, name)| (class.to_string(), name.to_string()))
+            .collect()
+    }
+
+    const FAILING_RUN: &str = "JUnit version 4.12
+..E.E
+Time: 1,234.5
+There were 2 failures:
+1) testAdd(CalcTest)
+java.lang.AssertionError: expected:<4> but was:<5>
+\tat org.junit.Assert.fail(Assert.java:88)
+\tat org.junit.Assert.assertEquals(Assert.java:118)
+\tat CalcTest.testAdd(CalcTest.java:12)
+\tat sun.reflect.NativeMethodAccessorImpl.invoke0(Native Method)
+2) testDivide(CalcTest)
+java.lang.ArithmeticException: / by zero
+\tat Calc.divide(Calc.java:7)
+\tat CalcTest.testDivide(CalcTest.java:20)
+\t... 25 more
+
+FAILURES!!!
+Tests run: 3,  Failures: 2
+";
+
+    #[test]
+    fn failures_errors_and_passes() {
+        let tests = known(&[
+            ("CalcTest", "testAdd"),
+            ("CalcTest", "testDivide"),
+            ("CalcTest", "testSub"),
+        ]);
+        let report = parse_junit_output(FAILING_RUN, &tests);
+        assert_eq!(report.outcome, TestOutcome::TestsFailed);
+        assert_eq!(report.tests_run, 3);
+        assert_eq!(report.failures, 2);
+        assert_eq!(report.time_secs, Some(1234.5));
+        assert_eq!(report.tests.len(), 3);
+
+        let add = &report.tests[0];
+        assert_eq!(
+            (add.class.as_str(), add.name.as_str()),
+            ("CalcTest", "testAdd")
+        );
+        assert_eq!(add.status, CaseStatus::Fail);
+        assert_eq!(
+            add.message.as_deref(),
+            Some("java.lang.AssertionError: expected:<4> but was:<5>")
+        );
+        // the framework frames are gone
+        assert_eq!(add.stack_trace, ["CalcTest.testAdd(CalcTest.java:12)"]);
+
+        let divide = &report.tests[1];
+        assert_eq!(divide.status, CaseStatus::Error);
+        assert_eq!(
+            divide.stack_trace,
+            [
+                "Calc.divide(Calc.java:7)",
+                "CalcTest.testDivide(CalcTest.java:20)"
+            ]
+        );
+
+        let sub = &report.tests[2];
+        assert_eq!(sub.name, "testSub");
+        assert_eq!(sub.status, CaseStatus::Pass);
+    }
+
+    #[test]
+    fn tests_of_a_class_that_could_not_be_initialized_did_not_run() {
+        let output = "JUnit version 4.12
+.E.
+Time: 0.003
+There was 1 failure:
+1) initializationError(CalcTest)
+java.lang.ExceptionInInitializerError
+\tat CalcTest.<clinit>(CalcTest.java:5)
+Caused by: java.lang.NullPointerException
+\tat Calc.<init>(Calc.java:3)
+
+FAILURES!!!
+Tests run: 2,  Failures: 1
+";
+        let tests = known(&[
+            ("CalcTest", "testAdd"),
+            ("CalcTest", "testSub"),
+            ("DeckTest", "testSize"),
+        ]);
+        let report = parse_junit_output(output, &tests);
+        assert_eq!(report.outcome, TestOutcome::TestsFailed);
+        assert_eq!(report.passed(), 1);
+        let status = |name: &str| {
+            report
+                .tests
+                .iter()
+                .find(|t| t.name == name)
+                .map(|t| t.status)
+        };
+        assert_eq!(status("initializationError"), Some(CaseStatus::Error));
+        assert_eq!(status("testAdd"), Some(CaseStatus::NotRun));
+        assert_eq!(status("testSub"), Some(CaseStatus::NotRun));
+        assert_eq!(status("testSize"), Some(CaseStatus::Pass));
+        assert_eq!(report.failed_tests().count(), 1);
+        let summary = report.summary();
+        assert!(
+            summary.contains("[NOT RUN] CalcTest.testAdd"),
+            "{}",
+            summary
+        );
+        assert!(!summary.contains("[PASS] CalcTest"), "{}", summary);
+    }
+
+    #[test]
+    fn all_passing() {
+        let output = "JUnit version 4.12\n..\nTime: 0,012\n\nOK (2 tests)\n\n";
+        let report = parse_junit_output(output, &known(&[("T", "a"), ("T", "b")]));
+        assert_eq!(report.outcome, TestOutcome::Passed);
+        assert_eq!(report.tests_run, 2);
+        assert_eq!(report.failures, 0);
+        assert_eq!(report.time_secs, Some(0.012));
+        assert_eq!(report.passed(), 2);
+    }
+
+    #[test]
+    fn output_without_a_summary() {
+        let report = parse_junit_output("Error: Could not find or load main class\n", &[]);
+        assert_eq!(report.outcome, TestOutcome::ExecutionFailed);
+        assert!(report.message.is_some());
+    }
+
+    #[test]
+    fn junit_times_in_every_locale() {
+        assert_eq!(parse_junit_time("0.012"), Some(0.012));
+        assert_eq!(parse_junit_time("0,012"), Some(0.012));
+        assert_eq!(parse_junit_time("1,234.5"), Some(1234.5));
+        assert_eq!(parse_junit_time("soon"), None);
+    }
+
+    #[test]
+    fn javac_diagnostics_with_context() {
+        let output = "src/Calc.java:5: error: cannot find symbol
+        return a + c;
+                   ^
+  symbol:   variable c
+  location: class Calc
+src/Calc.java:9: warning: [unchecked] unchecked call
+        list.add(x);
+                ^
+1 error
+1 warning
+";
+        let diagnostics = parse_javac_output(output);
+        assert_eq!(diagnostics.len(), 2);
+        let error = &diagnostics[0];
+        assert_eq!(error.file, "src/Calc.java");
+        assert_eq!(error.line, Some(5));
+        assert_eq!(error.kind, "error");
+        assert_eq!(error.message, "cannot find symbol");
+        assert_eq!(
+            error.context,
+            [
+                "        return a + c;",
+                "                   ^",
+                "  symbol:   variable c",
+                "  location: class Calc"
+            ]
+        );
+        let warning = &diagnostics[1];
+        assert_eq!(warning.kind, "warning");
+        assert_eq!(warning.line, Some(9));
+        // the footer belongs to no diagnostic
+        assert_eq!(warning.context.len(), 2);
+    }
+
+    #[test]
+    fn lines_that_only_look_like_diagnostics() {
+        assert!(parse_javac_output("Note: Calc.java uses unchecked operations.\n").is_empty());
+        assert!(parse_javac_output("Foo.java:3: note: something\n").is_empty());
+    }
+
+    #[test]
+    fn test_methods_on_one_or_two_lines() {
+        let source = "public class CalcTest {
+    @Test
+    public void testAdd() {
+    }
+
+    @Test(expected = ArithmeticException.class)
+    public void testDivide() throws Exception {}
+
+    @Test public void testSub() {}
+
+    public void helper() {}
+
+    @Before
+    public void setUp() {}
+}
+";
+        assert_eq!(
+            find_test_methods(source),
+            ["testAdd", "testDivide", "testSub"]
+        );
+    }
+}
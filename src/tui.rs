@@ -0,0 +1,508 @@
+use crate::config::Config;
+use crate::editor::edit_feedback;
+use crate::github_api::IssueOutcome;
+use crate::grading::GradeStatus;
+use crate::json_parser::Payload;
+use crate::review::{ReviewItem, ReviewState, load_review_items};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const HELP: &str = "↑/↓ student  ←/→ file  Tab pane  PgUp/PgDn scroll  a approve  e edit  s status  n next open  r reject  u undo  p post approved  q quit";
+
+//one row of the student list, item is None until the student has been graded
+struct Student {
+    payload: Payload,
+    item: Option<ReviewItem>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Source,
+    Feedback,
+    Tests,
+}
+
+struct App {
+    students: Vec<Student>,
+    list: ListState,
+    output_dir: PathBuf,
+    file: usize,
+    focus: Pane,
+    // scroll offsets of the source, feedback and tests panes
+    scroll: [u16; 3],
+    message: String,
+}
+
+//`imagi tui`: the payloads of json_dir next to the review items of output_dir.
+//Every change is saved to the review item right away, like in `imagi review`.
+pub async fn run_tui(
+    config: &Config,
+    json_dir: PathBuf,
+    output_dir: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = App::load(&json_dir, output_dir)?;
+    let mut terminal = ratatui::init();
+    let result = app.run(config, &mut terminal).await;
+    ratatui::restore();
+    result?;
+
+    let approved = app
+        .students
+        .iter()
+        .filter(|s| s.state() == Some(ReviewState::Approved))
+        .count();
+    if approved > 0 {
+        println!(
+            "🗂️  {} approved item(s) are not posted yet, press p in the TUI or run `imagi review -o {}`",
+            approved,
+            app.output_dir.display()
+        );
+    }
+    Ok(())
+}
+
+impl Student {
+    fn state(&self) -> Option<ReviewState> {
+        self.item.as_ref().map(|item| item.state)
+    }
+}
+
+impl App {
+    fn load(json_dir: &Path, output_dir: PathBuf) -> Result<App, Box<dyn std::error::Error>> {
+        let mut items: BTreeMap<String, ReviewItem> = load_review_items(&output_dir)?
+            .into_iter()
+            .map(|item| (item.student_id.clone(), item))
+            .collect();
+        let mut students = Vec::new();
+        for entry in fs::read_dir(json_dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|e| e == "json") {
+                let payload: Payload = serde_json::from_str(&fs::read_to_string(&path)?)
+                    .map_err(|e| format!("{} is not a payload: {}", path.display(), e))?;
+                let item = items.remove(&payload.user_id);
+                students.push(Student { payload, item });
+            }
+        }
+        if students.is_empty() {
+            return Err(format!("No payloads found in {}", json_dir.display()).into());
+        }
+        students.sort_by(|a, b| a.payload.user_id.cmp(&b.payload.user_id));
+
+        let mut list = ListState::default();
+        list.select(Some(0));
+        Ok(App {
+            students,
+            list,
+            output_dir,
+            file: 0,
+            focus: Pane::Source,
+            scroll: [0; 3],
+            message: String::new(),
+        })
+    }
+
+    async fn run(
+        &mut self,
+        config: &Config,
+        terminal: &mut DefaultTerminal,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.message.clear();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up => self.select(self.selected().saturating_sub(1)),
+                KeyCode::Down => self.select(self.selected() + 1),
+                KeyCode::Left => self.switch_file(-1),
+                KeyCode::Right => self.switch_file(1),
+                KeyCode::Tab => {
+                    self.focus = match self.focus {
+                        Pane::Source => Pane::Feedback,
+                        Pane::Feedback => Pane::Tests,
+                        Pane::Tests => Pane::Source,
+                    }
+                }
+                KeyCode::PageDown => self.scroll_focused(10),
+                KeyCode::PageUp => self.scroll_focused(-10),
+                KeyCode::Char('n') => self.next_open(),
+                KeyCode::Char('a') => self.set_state(ReviewState::Approved)?,
+                KeyCode::Char('r') => self.set_state(ReviewState::Rejected)?,
+                KeyCode::Char('u') => self.set_state(ReviewState::Pending)?,
+                KeyCode::Char('s') => self.cycle_status()?,
+                KeyCode::Char('e') => {
+                    suspend()?;
+                    let edited = self.edit();
+                    resume(terminal)?;
+                    edited?;
+                }
+                KeyCode::Char('p') => {
+                    suspend()?;
+                    let posted = self.post_approved(config).await;
+                    resume(terminal)?;
+                    posted?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn selected(&self) -> usize {
+        self.list.selected().unwrap_or(0)
+    }
+
+    fn current(&mut self) -> &mut Student {
+        let index = self.selected();
+        &mut self.students[index]
+    }
+
+    fn select(&mut self, index: usize) {
+        self.list
+            .select(Some(index.min(self.students.len().saturating_sub(1))));
+        self.file = 0;
+        self.scroll = [0; 3];
+    }
+
+    fn switch_file(&mut self, step: isize) {
+        let count = self.current().payload.source_files.len();
+        if count > 0 {
+            self.file = (self.file as isize + step).rem_euclid(count as isize) as usize;
+            self.scroll[0] = 0;
+        }
+    }
+
+    fn scroll_focused(&mut self, lines: i32) {
+        let offset = &mut self.scroll[self.focus as usize];
+        *offset = (*offset as i32 + lines).max(0) as u16;
+    }
+
+    // the "skip" of the TUI: jump to the next student that still needs a decision
+    fn next_open(&mut self) {
+        let count = self.students.len();
+        let start = self.selected();
+        let next = (1..=count)
+            .map(|step| (start + step) % count)
+            .find(|&i| self.students[i].state() == Some(ReviewState::Pending));
+        match next {
+            Some(index) => self.select(index),
+            None => self.message = "No pending students left".to_string(),
+        }
+    }
+
+    // the item of the selected student if it can still be changed, otherwise why not
+    fn open_item(&mut self) -> Result<&mut ReviewItem, String> {
+        let student = self.current();
+        let name = student.payload.user_id.clone();
+        match &mut student.item {
+            None => Err(format!(
+                "{} is not graded yet, run imagi generate first",
+                name
+            )),
+            Some(item) if item.state == ReviewState::Posted => {
+                Err(format!("The issue for {} is already posted", name))
+            }
+            Some(item) => Ok(item),
+        }
+    }
+
+    fn set_state(&mut self, state: ReviewState) -> Result<(), Box<dyn std::error::Error>> {
+        let output_dir = self.output_dir.clone();
+        match self.open_item() {
+            Ok(item) => {
+                item.state = state;
+                item.save(&output_dir)?;
+            }
+            Err(e) => self.message = e,
+        }
+        Ok(())
+    }
+
+    fn cycle_status(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let output_dir = self.output_dir.clone();
+        match self.open_item() {
+            Ok(item) => {
+                item.status = match item.status {
+                    GradeStatus::Pass => GradeStatus::Komplettering,
+                    GradeStatus::Komplettering => GradeStatus::Fail,
+                    GradeStatus::Fail | GradeStatus::NotGraded => GradeStatus::Pass,
+                };
+                item.save(&output_dir)?;
+            }
+            Err(e) => self.message = e,
+        }
+        Ok(())
+    }
+
+    fn edit(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let output_dir = self.output_dir.clone();
+        let item = match self.open_item() {
+            Ok(item) => item,
+            Err(e) => {
+                self.message = e;
+                return Ok(());
+            }
+        };
+        match edit_feedback(item)? {
+            Some((status, body)) => {
+                item.status = status;
+                item.body = Some(body);
+                item.save(&output_dir)?;
+                self.message = "Edits saved, press a to approve".to_string();
+            }
+            None => self.message = "Editor aborted, nothing changed".to_string(),
+        }
+        Ok(())
+    }
+
+    // Posts in the normal terminal, so the output of send_issue is readable
+    async fn post_approved(&mut self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut posted, mut written, mut failed) = (0, 0, 0);
+        for student in &mut self.students {
+            if let Some(item) = student
+                .item
+                .as_mut()
+                .filter(|i| i.state == ReviewState::Approved)
+            {
+                println!("📬 Posting {} ({})", item.student_id, item.status);
+                match item.post(config, &self.output_dir).await? {
+                    Some(IssueOutcome::Created) => posted += 1,
+                    Some(IssueOutcome::DryRun(_)) => written += 1,
+                    Some(IssueOutcome::Rejected) | None => failed += 1,
+                }
+            }
+        }
+        self.message = if config.dry_run.enabled {
+            format!(
+                "Dry run: {} issue(s) written to {}, {} failed, all still approved",
+                written,
+                config.dry_run.outbox.display(),
+                failed
+            )
+        } else {
+            format!(
+                "Posted {}, {} failed and are still approved",
+                posted, failed
+            )
+        };
+        if posted + written + failed > 0 {
+            print!("\nPress Enter to return");
+            io::stdout().flush()?;
+            io::stdin().read_line(&mut String::new())?;
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [list, right] =
+            Layout::horizontal([Constraint::Length(30), Constraint::Min(0)]).areas(main);
+        let [top, tests] =
+            Layout::vertical([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(right);
+        let [source, feedback] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(top);
+
+        self.draw_list(frame, list);
+        self.draw_source(frame, source);
+        self.draw_feedback(frame, feedback);
+        self.draw_tests(frame, tests);
+
+        let bottom = if self.message.is_empty() {
+            Line::from(HELP).style(Style::default().fg(Color::DarkGray))
+        } else {
+            Line::from(self.message.as_str()).style(Style::default().fg(Color::Yellow))
+        };
+        frame.render_widget(bottom, help);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<ListItem> = self
+            .students
+            .iter()
+            .map(|student| {
+                let line = match &student.item {
+                    Some(item) => Line::from(vec![
+                        Span::raw(format!("{} ", state_marker(item.state))),
+                        Span::raw(format!("{:<12} ", student.payload.user_id)),
+                        Span::styled(
+                            item.status.as_str(),
+                            Style::default().fg(status_color(item.status)),
+                        ),
+                    ]),
+                    None => Line::from(vec![
+                        Span::raw("  "),
+                        Span::raw(format!("{:<12} ", student.payload.user_id)),
+                        Span::styled("not graded", Style::default().fg(Color::DarkGray)),
+                    ]),
+                };
+                ListItem::new(line)
+            })
+            .collect();
+        let list = List::new(rows)
+            .block(Block::bordered().title(" Students "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.list);
+    }
+
+    fn draw_source(&self, frame: &mut Frame, area: Rect) {
+        let payload = &self.students[self.selected()].payload;
+        let (title, text) = match payload.source_files.get(self.file) {
+            Some(file) => (
+                format!(
+                    " {} ({}/{}) ",
+                    file.filename,
+                    self.file + 1,
+                    payload.source_files.len()
+                ),
+                numbered_lines(&file.content),
+            ),
+            None => (" No source files ".to_string(), Vec::new()),
+        };
+        let source = Paragraph::new(text)
+            .block(self.pane_block(Pane::Source, title))
+            .scroll((self.scroll[Pane::Source as usize], 0));
+        frame.render_widget(source, area);
+    }
+
+    fn draw_feedback(&self, frame: &mut Frame, area: Rect) {
+        let student = &self.students[self.selected()];
+        let (title, text) = match &student.item {
+            Some(item) => {
+                let mut title = format!(
+                    " {} {} ({:.0}%), {} ",
+                    item.status.emoji(),
+                    item.status,
+                    item.confidence * 100.0,
+                    state_name(item.state)
+                );
+                if item.body.is_some() {
+                    title.push_str("edited ");
+                }
+                (title, item.issue_body())
+            }
+            None => (" Feedback ".to_string(), "Not graded yet.".to_string()),
+        };
+        let feedback = Paragraph::new(text)
+            .block(self.pane_block(Pane::Feedback, title))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll[Pane::Feedback as usize], 0));
+        frame.render_widget(feedback, area);
+    }
+
+    fn draw_tests(&self, frame: &mut Frame, area: Rect) {
+        let payload = &self.students[self.selected()].payload;
+        // Payloads from older versions have no summary
+        let text = if payload.test_summary.is_empty() {
+            payload.test_results.as_str()
+        } else {
+            payload.test_summary.as_str()
+        };
+        let title = match &payload.commit {
+            Some(commit) => format!(" Tests ({}) ", &commit[..commit.len().min(8)]),
+            None => " Tests ".to_string(),
+        };
+        let tests = Paragraph::new(text)
+            .block(self.pane_block(Pane::Tests, title))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll[Pane::Tests as usize], 0));
+        frame.render_widget(tests, area);
+    }
+
+    fn pane_block(&self, pane: Pane, title: String) -> Block<'static> {
+        let block = Block::bordered().title(title);
+        if self.focus == pane {
+            block.border_style(Style::default().fg(Color::Yellow))
+        } else {
+            block
+        }
+    }
+}
+
+// Leaves the TUI for the editor or posting, resume switches back
+fn suspend() -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)
+}
+
+fn resume(terminal: &mut DefaultTerminal) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    terminal.clear()
+}
+
+// Numbered, so issues like "Card.java:12" can be found. The payload keeps the lines of the
+// student's file (comments are blanked, not removed), so the numbers are the same.
+fn numbered_lines(content: &str) -> Vec<Line<'static>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| Line::from(format!("{:>4} │ {}", i + 1, line)))
+        .collect()
+}
+
+fn state_marker(state: ReviewState) -> &'static str {
+    match state {
+        ReviewState::Pending => "•",
+        ReviewState::Approved => "+",
+        ReviewState::Posted => "✓",
+        ReviewState::Rejected => "✗",
+    }
+}
+
+fn state_name(state: ReviewState) -> &'static str {
+    match state {
+        ReviewState::Pending => "pending",
+        ReviewState::Approved => "approved",
+        ReviewState::Posted => "posted",
+        ReviewState::Rejected => "rejected",
+    }
+}
+
+fn status_color(status: GradeStatus) -> Color {
+    match status {
+        GradeStatus::Pass => Color::Green,
+        GradeStatus::Fail => Color::Red,
+        GradeStatus::Komplettering => Color::Yellow,
+        GradeStatus::NotGraded => Color::DarkGray,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_parser::parse_source_file;
+
+    #[test]
+    fn source_lines_are_numbered_as_in_the_students_file() {
+        let original = "/**\n * Deck of cards.\n */\npublic class Deck {\n    /* shuffled\n       later */\n    int size() { return 52; }\n}\n";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Deck.java");
+        fs::write(&path, original).unwrap();
+        let file = parse_source_file("Deck.java", &path).unwrap();
+
+        let shown: Vec<String> = numbered_lines(&file.content)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(shown.len(), original.lines().count());
+        assert_eq!(shown[3], "   4 │ public class Deck {");
+        assert_eq!(shown[6], "   7 │     int size() { return 52; }");
+    }
+}
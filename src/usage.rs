@@ -0,0 +1,355 @@
+use crate::budget::estimate_tokens;
+use crate::config::{Config, ModelPrice};
+use crate::llm::Completion;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// USD per million input/output tokens from the providers' price lists,
+// llm.prices in the config adds models and overrides these
+const BUILTIN_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+    ("gemini-2.5-pro", 1.25, 10.00),
+];
+
+//tokens and time spent on one student, summed over all attempts
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Usage {
+    pub requests: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    // time spent waiting for the model, retries included
+    pub latency_ms: u64,
+    // the server reported no usage for some request, those tokens are estimated
+    pub estimated: bool,
+}
+
+impl Usage {
+    pub fn record(&mut self, prompt: &str, completion: &Completion, elapsed: Duration) {
+        self.requests += 1;
+        self.latency_ms += elapsed.as_millis() as u64;
+        self.estimated |=
+            completion.prompt_tokens.is_none() || completion.completion_tokens.is_none();
+        self.prompt_tokens += completion
+            .prompt_tokens
+            .unwrap_or_else(|| estimate_tokens(prompt) as u64);
+        self.completion_tokens += completion
+            .completion_tokens
+            .unwrap_or_else(|| estimate_tokens(&completion.text) as u64);
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.latency_ms += other.latency_ms;
+        self.estimated |= other.estimated;
+    }
+
+    fn cost(&self, price: Option<ModelPrice>) -> Option<f64> {
+        price.map(|p| {
+            (self.prompt_tokens as f64 * p.input_per_million
+                + self.completion_tokens as f64 * p.output_per_million)
+                / 1_000_000.0
+        })
+    }
+}
+
+//one student of a run, saved in <output>/usage.json
+#[derive(Serialize, Deserialize)]
+pub struct StudentUsage {
+    pub student_id: String,
+    pub task: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+    // None if the model has no price
+    pub cost_usd: Option<f64>,
+    // false if the student could not be graded, the requests were paid for anyway
+    pub graded: bool,
+}
+
+//one line of the ledger, per task and generate run
+#[derive(Serialize, Deserialize)]
+pub struct LedgerEntry {
+    // RFC3339
+    pub started_at: String,
+    pub finished_at: String,
+    pub task: String,
+    pub provider: String,
+    pub model: String,
+    pub output: PathBuf,
+    pub students: usize,
+    pub failed: usize,
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub cost_usd: Option<f64>,
+}
+
+//Everything the model was asked during one `imagi generate` run
+pub struct UsageSession {
+    started_at: String,
+    provider: String,
+    model: String,
+    price: Option<ModelPrice>,
+    students: Vec<StudentUsage>,
+}
+
+impl UsageSession {
+    pub fn new(config: &Config, provider: &str, model: &str) -> UsageSession {
+        UsageSession {
+            started_at: Local::now().to_rfc3339(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            price: model_price(config, provider, model),
+            students: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, student: &str, task: &str, usage: Usage, graded: bool) {
+        if usage.requests == 0 {
+            return;
+        }
+        self.students.push(StudentUsage {
+            student_id: student.to_string(),
+            task: task.to_string(),
+            cost_usd: usage.cost(self.price),
+            usage,
+            graded,
+        });
+    }
+
+    //<output>/usage.json with the students of this run, written after every student so it
+    //survives a run that is stopped halfway
+    pub fn save(&self, output_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if self.students.is_empty() {
+            return Ok(());
+        }
+        fs::write(
+            output_dir.join("usage.json"),
+            serde_json::to_string_pretty(&self.students)?,
+        )?;
+        Ok(())
+    }
+
+    //Appends one line per task to usage.ledger
+    pub fn append_to_ledger(
+        &self,
+        config: &Config,
+        output_dir: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.students.is_empty() {
+            return Ok(());
+        }
+        let finished_at = Local::now().to_rfc3339();
+        let mut lines = String::new();
+        for (task, students) in self.by_task() {
+            let usage = total(&students);
+            let entry = LedgerEntry {
+                started_at: self.started_at.clone(),
+                finished_at: finished_at.clone(),
+                task: task.to_string(),
+                provider: self.provider.clone(),
+                model: self.model.clone(),
+                output: output_dir.to_path_buf(),
+                students: students.len(),
+                failed: students.iter().filter(|s| !s.graded).count(),
+                cost_usd: usage.cost(self.price),
+                usage,
+            };
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+        let ledger = &config.usage.ledger;
+        if let Some(dir) = ledger.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(ledger)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .map_err(|e| {
+                format!(
+                    "Could not write the usage ledger {}: {}",
+                    ledger.display(),
+                    e
+                )
+            })?;
+        Ok(())
+    }
+
+    pub fn print_summary(&self, config: &Config, output_dir: &Path) {
+        if self.students.is_empty() {
+            println!("💰 No requests to {} in this run", self.model);
+            return;
+        }
+        let usage = total(&self.students.iter().collect::<Vec<_>>());
+        println!(
+            "\n\x1b[1;34m💰 Usage ({} {}):\x1b[0m",
+            self.provider, self.model
+        );
+        println!(
+            "   {} student(s), {} request(s), {} prompt + {} completion tokens{}",
+            self.students.len(),
+            usage.requests,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            if usage.estimated {
+                " (partly estimated)"
+            } else {
+                ""
+            }
+        );
+        println!(
+            "   {:.1}s waiting for the model, {:.1}s per request",
+            usage.latency_ms as f64 / 1000.0,
+            usage.latency_ms as f64 / 1000.0 / usage.requests as f64
+        );
+        match usage.cost(self.price) {
+            Some(cost) => println!(
+                "   Cost: \x1b[1m{}\x1b[0m ({} per student)",
+                format_usd(cost),
+                format_usd(cost / self.students.len() as f64)
+            ),
+            None => println!(
+                "   \x1b[33mCost: unknown, no price for {} (set llm.prices.\"{}\" in the config)\x1b[0m",
+                self.model, self.model
+            ),
+        }
+        println!(
+            "   \x1b[90mPer student: {}, ledger: {}\x1b[0m",
+            output_dir.join("usage.json").display(),
+            config.usage.ledger.display()
+        );
+    }
+
+    fn by_task(&self) -> BTreeMap<&str, Vec<&StudentUsage>> {
+        let mut tasks: BTreeMap<&str, Vec<&StudentUsage>> = BTreeMap::new();
+        for student in &self.students {
+            tasks.entry(&student.task).or_default().push(student);
+        }
+        tasks
+    }
+}
+
+//ledger lines of one task and model summed up
+struct LedgerRow {
+    runs: usize,
+    students: usize,
+    usage: Usage,
+    cost: Option<f64>,
+}
+
+//`imagi usage`: the ledger summed up per task and model
+pub fn print_ledger(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let ledger = &config.usage.ledger;
+    if !ledger.exists() {
+        println!(
+            "No usage recorded yet ({} does not exist)",
+            ledger.display()
+        );
+        return Ok(());
+    }
+    // (task, model) -> the runs summed up
+    let mut rows: BTreeMap<(String, String), LedgerRow> = BTreeMap::new();
+    for (number, line) in fs::read_to_string(ledger)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LedgerEntry = serde_json::from_str(line)
+            .map_err(|e| format!("{} line {}: {}", ledger.display(), number + 1, e))?;
+        let row = rows
+            .entry((entry.task, entry.model))
+            .or_insert_with(|| LedgerRow {
+                runs: 0,
+                students: 0,
+                usage: Usage::default(),
+                cost: Some(0.0),
+            });
+        row.runs += 1;
+        row.students += entry.students;
+        row.usage.add(&entry.usage);
+        // one run without a price makes the total unknown
+        row.cost = row.cost.zip(entry.cost_usd).map(|(a, b)| a + b);
+    }
+
+    println!("\x1b[1;34m💰 Usage from {}\x1b[0m", ledger.display());
+    println!(
+        "   {:<14} {:<22} {:>5} {:>9} {:>13} {:>13} {:>10}",
+        "Task", "Model", "Runs", "Students", "Prompt tok", "Output tok", "Cost"
+    );
+    let mut total_cost = 0.0;
+    let mut unpriced = 0;
+    for ((task, model), row) in &rows {
+        println!(
+            "   {:<14} {:<22} {:>5} {:>9} {:>13} {:>13} {:>10}",
+            task,
+            model,
+            row.runs,
+            row.students,
+            row.usage.prompt_tokens,
+            row.usage.completion_tokens,
+            row.cost
+                .map(format_usd)
+                .unwrap_or_else(|| "unknown".to_string())
+        );
+        match row.cost {
+            Some(cost) => total_cost += cost,
+            None => unpriced += 1,
+        }
+    }
+    if unpriced == 0 {
+        println!("   Total: {}", format_usd(total_cost));
+    } else {
+        println!(
+            "   Total: {} \x1b[33m(without {} row(s) that have no price)\x1b[0m",
+            format_usd(total_cost),
+            unpriced
+        );
+    }
+    Ok(())
+}
+
+// llm.prices first, then the built-in list. Local models are free unless they are given a price.
+fn model_price(config: &Config, provider: &str, model: &str) -> Option<ModelPrice> {
+    if let Some(price) = config.llm.prices.get(model) {
+        return Some(*price);
+    }
+    if let Some((_, input, output)) = BUILTIN_PRICES.iter().find(|(name, _, _)| *name == model) {
+        return Some(ModelPrice {
+            input_per_million: *input,
+            output_per_million: *output,
+        });
+    }
+    provider.starts_with("local:").then_some(ModelPrice {
+        input_per_million: 0.0,
+        output_per_million: 0.0,
+    })
+}
+
+fn total(students: &[&StudentUsage]) -> Usage {
+    let mut usage = Usage::default();
+    for student in students {
+        usage.add(&student.usage);
+    }
+    usage
+}
+
+// Cents are too coarse for a single student
+fn format_usd(cost: f64) -> String {
+    if cost < 1.0 {
+        format!("${:.4}", cost)
+    } else {
+        format!("${:.2}", cost)
+    }
+}